@@ -53,8 +53,8 @@ impl Bounds {
         }
     }
     //fix this later
-    pub fn fitPoints(&mut self, points: Vec<Vector3<f64>>) {
-        for point in points.iter() {
+    pub fn fitPoints<I: IntoIterator<Item = Vector3<f64>>>(&mut self, points: I) {
+        for point in points {
             self.max.x = self.max.x.max(point.x);
             self.max.y = self.max.y.max(point.y);
             self.max.z = self.max.z.max(point.z);
@@ -0,0 +1,253 @@
+use crate::imageio::ImageChannel;
+use crate::render::Framebuffer;
+use crate::rng::hash64;
+use crate::vectors::Vector3;
+use crate::vectors::Vector3 as Color;
+
+// Arbitrary output variables: what the camera rays hit first, kept next to the beauty pass for
+// compositing and as guides for denoising
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Aov {
+    Depth,
+    Normal,
+    Albedo,
+    Position,
+    ObjectId,
+    MaterialId,
+}
+impl Aov {
+    pub const ALL: [Aov; 6] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::Position,
+        Aov::ObjectId,
+        Aov::MaterialId,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+        }
+    }
+    // EXR channel names, the ones compositors look for
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal => &["N.X", "N.Y", "N.Z"],
+            Aov::Albedo => &["albedo.R", "albedo.G", "albedo.B"],
+            Aov::Position => &["P.X", "P.Y", "P.Z"],
+            Aov::ObjectId => &["objectId"],
+            Aov::MaterialId => &["materialId"],
+        }
+    }
+    // The pixel's value for each of channels(). Ids are -1 where the pixel saw nothing
+    pub fn values(self, p: &AovPixel) -> [f32; 3] {
+        let v = |c: Vector3<f64>| [c.x as f32, c.y as f32, c.z as f32];
+        let id = |i: Option<u32>| [i.map_or(-1.0, |i| i as f32), 0.0, 0.0];
+        match self {
+            Aov::Depth => [p.depth() as f32, 0.0, 0.0],
+            Aov::Normal => v(p.normal()),
+            Aov::Albedo => v(p.albedo()),
+            Aov::Position => v(p.position()),
+            Aov::ObjectId => id(p.ids.map(|ids| ids.0)),
+            Aov::MaterialId => id(p.ids.map(|ids| ids.1)),
+        }
+    }
+}
+
+// What one camera ray hit first. depth is the distance from the camera, the normal is the
+// shading normal facing the ray, ids are (object, material)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AovSample {
+    pub depth: f64,
+    pub normal: Vector3<f64>,
+    pub position: Vector3<f64>,
+    pub albedo: Color<f64>,
+    pub ids: Option<(u32, u32)>,
+}
+impl AovSample {
+    // Rays that escape only have the background's color to offer
+    pub fn miss(background: Color<f64>) -> AovSample {
+        AovSample {
+            depth: std::f64::INFINITY,
+            normal: Vector3::zero(),
+            position: Vector3::zero(),
+            albedo: background,
+            ids: None,
+        }
+    }
+}
+
+// A pixel's samples, box filtered whatever the pixel filter is. Depth, normal and position average
+// over the samples that hit something and albedo over all of them. Ids can't be averaged, so the
+// pixel keeps its first hit's
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AovPixel {
+    pub count: u32,
+    pub hits: u32,
+    pub depth: f64,
+    pub normal: Vector3<f64>,
+    pub position: Vector3<f64>,
+    pub albedo: Color<f64>,
+    pub ids: Option<(u32, u32)>,
+}
+impl AovPixel {
+    pub fn new() -> AovPixel {
+        AovPixel {
+            count: 0,
+            hits: 0,
+            depth: 0.0,
+            normal: Vector3::zero(),
+            position: Vector3::zero(),
+            albedo: Color::zero(),
+            ids: None,
+        }
+    }
+    pub fn add(&mut self, s: &AovSample) {
+        self.count += 1;
+        self.albedo = self.albedo + s.albedo;
+        if s.ids.is_some() {
+            self.hits += 1;
+            self.depth += s.depth;
+            self.normal = self.normal + s.normal;
+            self.position = self.position + s.position;
+            self.ids = self.ids.or(s.ids);
+        }
+    }
+    // o's samples come after self's
+    pub fn merge(&self, o: &AovPixel) -> AovPixel {
+        AovPixel {
+            count: self.count + o.count,
+            hits: self.hits + o.hits,
+            depth: self.depth + o.depth,
+            normal: self.normal + o.normal,
+            position: self.position + o.position,
+            albedo: self.albedo + o.albedo,
+            ids: self.ids.or(o.ids),
+        }
+    }
+    // Infinite where nothing was hit
+    pub fn depth(&self) -> f64 {
+        if self.hits == 0 {
+            return std::f64::INFINITY;
+        }
+        self.depth / self.hits as f64
+    }
+    pub fn normal(&self) -> Vector3<f64> {
+        &self.normal * (1.0 / self.hits.max(1) as f64)
+    }
+    pub fn position(&self) -> Vector3<f64> {
+        &self.position * (1.0 / self.hits.max(1) as f64)
+    }
+    pub fn albedo(&self) -> Color<f64> {
+        &self.albedo * (1.0 / self.count.max(1) as f64)
+    }
+}
+
+// Every AOV as named float channels, for a multi-channel EXR next to the beauty's R, G and B
+pub fn aov_channels(pixels: &[AovPixel]) -> Vec<ImageChannel> {
+    let mut channels = vec![];
+    for aov in Aov::ALL.iter() {
+        for (c, name) in aov.channels().iter().enumerate() {
+            channels.push(ImageChannel {
+                name: name.to_string(),
+                data: pixels.iter().map(|p| aov.values(p)[c]).collect(),
+            });
+        }
+    }
+    channels
+}
+
+// The raw values of one AOV as RGB, single channel ones repeated across all three
+pub fn aov_rgb(aov: Aov, pixels: &[AovPixel]) -> Vec<[f32; 3]> {
+    pixels
+        .iter()
+        .map(|p| {
+            let v = aov.values(p);
+            if aov.channels().len() == 1 {
+                [v[0]; 3]
+            } else {
+                v
+            }
+        })
+        .collect()
+}
+
+// A color that tells neighbouring ids apart
+fn id_color(id: u32) -> Color<f64> {
+    let h = hash64(id as u64 + 1);
+    let c = |shift: u32| 0.15 + 0.85 * ((h >> shift) & 0xff) as f64 / 255.0;
+    Color::from_tuple((c(0), c(8), c(16)))
+}
+
+// An AOV squeezed into something viewable for the LDR formats: depth from white up close to black
+// far away, normals and positions (within the bounds of what was hit) mapped into [0, 1], and a
+// color per id. Misses stay black
+pub fn aov_preview(aov: Aov, pixels: &[AovPixel], width: usize, height: usize) -> Framebuffer {
+    let mut fb = Framebuffer::new(width, height);
+    let hit: Vec<&AovPixel> = pixels.iter().filter(|p| p.hits > 0).collect();
+    let far = hit.iter().map(|p| p.depth()).fold(0.0, f64::max);
+    let (mut lo, mut hi) = (Vector3::<f64>::inf(), Vector3::<f64>::neg_inf());
+    for p in hit.iter() {
+        let q = p.position();
+        lo = Vector3::from_tuple((lo.x.min(q.x), lo.y.min(q.y), lo.z.min(q.z)));
+        hi = Vector3::from_tuple((hi.x.max(q.x), hi.y.max(q.y), hi.z.max(q.z)));
+    }
+    let extent = hi - lo;
+    for (out, p) in fb.pixels.iter_mut().zip(pixels.iter()) {
+        if p.hits == 0 && aov != Aov::Albedo {
+            continue;
+        }
+        *out = match aov {
+            Aov::Depth => &Color::one() * (1.0 - p.depth() / far.max(1e-9)),
+            Aov::Normal => &(p.normal() + Vector3::one()) * 0.5,
+            Aov::Albedo => p.albedo(),
+            Aov::Position => {
+                let q = p.position() - lo;
+                Vector3::from_tuple((
+                    q.x / extent.x.max(1e-9),
+                    q.y / extent.y.max(1e-9),
+                    q.z / extent.z.max(1e-9),
+                ))
+            }
+            Aov::ObjectId => id_color(p.ids.unwrap().0),
+            Aov::MaterialId => id_color(p.ids.unwrap().1),
+        };
+    }
+    fb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Positions are spread over the bounds of what was hit, each axis on its own
+    #[test]
+    fn position_preview_is_normalized_to_the_hit_bounds() {
+        let mut pixels = vec![];
+        for &p in [(0.0, 0.0, 0.0), (2.0, 1.0, 0.0), (1.0, 0.5, 4.0)].iter() {
+            let mut a = AovPixel::new();
+            a.add(&AovSample {
+                depth: 1.0,
+                normal: Vector3::up(),
+                position: Vector3::from_tuple(p),
+                albedo: Color::one(),
+                ids: Some((0, 0)),
+            });
+            pixels.push(a);
+        }
+        let mut miss = AovPixel::new();
+        miss.add(&AovSample::miss(Color::one()));
+        pixels.push(miss);
+        let fb = aov_preview(Aov::Position, &pixels, 4, 1);
+        assert_eq!(fb.pixels[0], Vector3::zero());
+        assert_eq!(fb.pixels[1], Vector3::from_tuple((1.0, 1.0, 0.0)));
+        assert_eq!(fb.pixels[2], Vector3::from_tuple((0.5, 0.5, 1.0)));
+        assert_eq!(fb.pixels[3], Vector3::zero());
+    }
+}
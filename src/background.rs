@@ -0,0 +1,92 @@
+use crate::imageio;
+use crate::imageio::HdrImage;
+use crate::ray::Ray;
+use crate::vectors::Vector3;
+use crate::vectors::Vector3 as Color;
+use std::f64::consts::PI;
+use std::io;
+use std::path::Path;
+
+// What a ray sees when it leaves the scene
+pub trait Background {
+    fn color(&self, r: &Ray) -> Color<f64>;
+}
+
+pub struct SolidBackground {
+    pub color: Color<f64>,
+}
+impl Background for SolidBackground {
+    fn color(&self, _r: &Ray) -> Color<f64> {
+        self.color
+    }
+}
+
+// Lerp on the ray's height, the original white-to-blue sky by default
+pub struct GradientBackground {
+    pub bottom: Color<f64>,
+    pub top: Color<f64>,
+}
+impl Default for GradientBackground {
+    fn default() -> GradientBackground {
+        GradientBackground {
+            bottom: Color::one(),
+            top: Color::from_tuple((0.5, 0.7, 1.0)),
+        }
+    }
+}
+impl Background for GradientBackground {
+    fn color(&self, r: &Ray) -> Color<f64> {
+        let t = 0.5 * (r.dir.normalized().y + 1.0);
+        (1.0 - t) * self.bottom + (t * self.top)
+    }
+}
+
+// Equirectangular (lat-long) map with +y up. rotation spins it around y, in degrees
+pub struct EnvironmentMap {
+    pub image: HdrImage,
+    pub rotation: f64,
+    pub intensity: f64,
+}
+impl EnvironmentMap {
+    pub fn load(path: &Path, rotation: f64, intensity: f64) -> io::Result<EnvironmentMap> {
+        Ok(EnvironmentMap {
+            image: imageio::read_hdr_image(path)?,
+            rotation,
+            intensity,
+        })
+    }
+    fn texel(&self, x: usize, y: usize) -> Color<f64> {
+        let p = self.image.pixels[y * self.image.width + x];
+        Color::from_tuple((p[0] as f64, p[1] as f64, p[2] as f64))
+    }
+    // Bilinear lookup, wrapping around in u and clamping at the poles
+    pub fn lookup(&self, u: f64, v: f64) -> Color<f64> {
+        let (w, h) = (self.image.width, self.image.height);
+        if w == 0 || h == 0 {
+            return Color::zero();
+        }
+        let fx = u * w as f64 - 0.5;
+        let fy = (v * h as f64 - 0.5).max(0.0).min((h - 1) as f64);
+        let (x0, y0) = (fx.floor(), fy.floor());
+        let (tx, ty) = (fx - x0, fy - y0);
+        let wrap = |x: f64| (x as i64).rem_euclid(w as i64) as usize;
+        let (xa, xb) = (wrap(x0), wrap(x0 + 1.0));
+        let (ya, yb) = (y0 as usize, (y0 as usize + 1).min(h - 1));
+        let top = (1.0 - tx) * self.texel(xa, ya) + tx * self.texel(xb, ya);
+        let bottom = (1.0 - tx) * self.texel(xa, yb) + tx * self.texel(xb, yb);
+        (1.0 - ty) * top + ty * bottom
+    }
+    pub fn direction_to_uv(&self, dir: &Vector3<f64>) -> (f64, f64) {
+        let d = dir.normalized();
+        let phi = d.x.atan2(-d.z) - self.rotation.to_radians();
+        let theta = d.y.max(-1.0).min(1.0).acos();
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        (u, theta / PI)
+    }
+}
+impl Background for EnvironmentMap {
+    fn color(&self, r: &Ray) -> Color<f64> {
+        let (u, v) = self.direction_to_uv(&r.dir);
+        self.intensity * self.lookup(u, v)
+    }
+}
@@ -40,8 +40,9 @@ impl Camera {
             lens_radius: aperature / 2.0,
         }
     }
-    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
-        let rd = self.lens_radius * Vector3::<f64>::random_in_unit_disk();
+    // lens is a uniform 2D sample for the point on the aperture
+    pub fn get_ray(&self, u: f64, v: f64, lens: (f64, f64)) -> Ray {
+        let rd = self.lens_radius * Vector3::<f64>::in_unit_disk_from(lens);
         let offset = rd.x * self.u + rd.y * self.v;
         Ray::new(
             self.origin + offset,
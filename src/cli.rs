@@ -0,0 +1,446 @@
+use raytracing_one_weekend::filter::FilterKind;
+use raytracing_one_weekend::imageio;
+use raytracing_one_weekend::imageio::ExrPixelType;
+use raytracing_one_weekend::render::{MAX_IMAGE_SIDE, MAX_PATH_DEPTH, MAX_SAMPLES};
+use raytracing_one_weekend::sampler::SamplerKind;
+use raytracing_one_weekend::tiles::TileOrder;
+use raytracing_one_weekend::tonemap::{ToneMapping, ToneOperator};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const USAGE: &str = "\
+USAGE:
+    raytracing_one_weekend [SUBCOMMAND] [OPTIONS]
+
+SUBCOMMANDS:
+    preview    Render into a window (default)
+    render     Render headless and write the image to --output
+    info       Print the scene and settings without rendering
+    help       Print this message
+
+OPTIONS:
+    -W, --width <px>         Image width [default: scene setting or 1200]
+    -H, --height <px>        Image height [default: scene setting or 800]
+    -s, --samples <n>        Samples per pixel [default: scene setting or 500]
+    -d, --max-depth <n>      Maximum bounce depth [default: scene setting or 50]
+        --rr-depth <n>       Bounces before Russian roulette may end a path [default: scene setting or 3]
+        --sampler <kind>     independent, stratified, halton or sobol [default: sobol]
+        --filter <kind>      Pixel filter: box, tent, gaussian, mitchell or lanczos [default: box]
+        --filter-radius <px> Filter radius in pixels [default: 0.5, 1, 1.5, 2 and 3 for the above]
+        --adaptive <err>     Stop sampling a pixel once its relative error is below err, --samples is the cap
+        --min-samples <n>    Samples every pixel gets before --adaptive may stop it [default: 16]
+        --seed <n>           Seed for scene generation and sampling [default: random]
+    -j, --threads <n>        Worker threads [default: one per core]
+        --tile-size <px>     Width and height of the tiles handed to the workers [default: 32]
+        --tile-order <order> scanline, spiral or hilbert [default: spiral]
+        --scene <name|file>  Built-in scene name or a .scene file [default: demo]
+        --envmap <file>      Light the scene with a .hdr/.pfm environment map instead of its background
+    -o, --output <file>      Output image for render, .exr/.pfm keep the linear HDR values [default: render.png]
+        --heatmap <file>     Also write the samples taken per pixel as a blue to red image
+        --aovs <file>        Also write depth, normal, albedo, position and object/material id passes,
+                             as channels next to R, G, B in an .exr or one image per pass otherwise
+        --exr-type <type>    half or float samples in .exr output [default: half]
+        --bit-depth <8|16>   Bits per channel for .png/.ppm output [default: 8]
+        --quality <1-100>    JPEG quality [default: 90]
+        --dither             Dither LDR output to hide banding
+        --denoise            Also write a denoised copy of the output, guided by the AOVs, as <output>.denoised.<ext>
+        --tonemap <op>       clamp, reinhard, reinhard-extended, hable or aces [default: clamp]
+        --exposure <ev>      Exposure in stops applied before tone mapping [default: 0]
+        --white-point <x>    Luminance reinhard-extended maps to white [default: 4]
+        --white-balance <K>  Color temperature of the light to neutralize [default: 6500]
+    -h, --help               Print this message
+";
+
+pub const SCENES: &[&str] = &["demo"];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Command {
+    Preview,
+    Render,
+    Info,
+    Help,
+}
+
+#[derive(Debug, Clone)]
+// Render settings are optional here so a scene file can fill in whatever wasn't given
+pub struct Options {
+    pub command: Command,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples: Option<u32>,
+    pub max_depth: Option<u32>,
+    pub rr_depth: Option<u32>,
+    pub sampler: SamplerKind,
+    pub filter: FilterKind,
+    pub filter_radius: Option<f64>,
+    pub adaptive: Option<f64>,
+    pub min_samples: u32,
+    pub seed: Option<u64>,
+    pub threads: Option<usize>,
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    pub scene: String,
+    pub envmap: Option<PathBuf>,
+    pub output: PathBuf,
+    pub heatmap: Option<PathBuf>,
+    pub aovs: Option<PathBuf>,
+    pub exr_type: ExrPixelType,
+    pub bit_depth: u8,
+    pub jpeg_quality: u8,
+    pub dither: bool,
+    pub denoise: bool,
+    pub tonemap: ToneMapping,
+}
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            command: Command::Preview,
+            width: None,
+            height: None,
+            samples: None,
+            max_depth: None,
+            rr_depth: None,
+            sampler: SamplerKind::Sobol,
+            filter: FilterKind::Box,
+            filter_radius: None,
+            adaptive: None,
+            min_samples: 16,
+            seed: None,
+            threads: None,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            scene: "demo".to_string(),
+            envmap: None,
+            output: PathBuf::from("render.png"),
+            heatmap: None,
+            aovs: None,
+            exr_type: ExrPixelType::Half,
+            bit_depth: 8,
+            jpeg_quality: 90,
+            dither: false,
+            denoise: false,
+            tonemap: ToneMapping::default(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    UnknownCommand(String),
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue {
+        option: String,
+        value: String,
+        reason: String,
+    },
+}
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::UnknownCommand(c) => write!(
+                f,
+                "unknown subcommand '{}', expected one of: preview, render, info, help",
+                c
+            ),
+            CliError::UnknownOption(o) => write!(f, "unknown option '{}'", o),
+            CliError::MissingValue(o) => write!(f, "option '{}' needs a value", o),
+            CliError::InvalidValue {
+                option,
+                value,
+                reason,
+            } => write!(f, "invalid value '{}' for '{}': {}", value, option, reason),
+        }
+    }
+}
+impl std::error::Error for CliError {}
+
+fn invalid(option: &str, value: &str, reason: &str) -> CliError {
+    CliError::InvalidValue {
+        option: option.to_string(),
+        value: value.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+// Parses a number and checks it against [min, max]
+fn parse_in_range<T>(option: &str, value: &str, min: T, max: T) -> Result<T, CliError>
+where
+    T: FromStr + PartialOrd + fmt::Display,
+{
+    let n: T = value
+        .parse()
+        .map_err(|_| invalid(option, value, "expected a number"))?;
+    // written this way round so NaN doesn't slip through for the float options
+    if !(n >= min && n <= max) {
+        return Err(invalid(
+            option,
+            value,
+            &format!("must be between {} and {}", min, max),
+        ));
+    }
+    Ok(n)
+}
+
+pub fn parse_args<I>(args: I) -> Result<Options, CliError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut opts = Options::default();
+    let mut args = args.into_iter().peekable();
+
+    let command = match args.peek().map(|a| a.as_str()) {
+        Some("preview") => Some(Command::Preview),
+        Some("render") => Some(Command::Render),
+        Some("info") => Some(Command::Info),
+        Some("help") => Some(Command::Help),
+        Some(a) if !a.starts_with('-') => return Err(CliError::UnknownCommand(a.to_string())),
+        _ => None,
+    };
+    if let Some(c) = command {
+        opts.command = c;
+        args.next();
+    }
+
+    while let Some(arg) = args.next() {
+        // Accept both "--opt value" and "--opt=value"
+        let (name, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+            _ => (arg.clone(), None),
+        };
+        if name == "-h" || name == "--help" {
+            opts.command = Command::Help;
+            continue;
+        }
+        if name == "--dither" {
+            opts.dither = true;
+            continue;
+        }
+        if name == "--denoise" {
+            opts.denoise = true;
+            continue;
+        }
+        let takes_value = matches!(
+            name.as_str(),
+            "-W" | "--width" | "-H" | "--height" | "-s" | "--samples" | "-d" | "--max-depth"
+                | "--rr-depth" | "--sampler" | "--filter" | "--filter-radius"
+                | "--adaptive" | "--min-samples" | "--heatmap" | "--aovs" | "--seed" | "-j" | "--threads" | "--tile-size" | "--tile-order"
+                | "--scene" | "--envmap"
+                | "-o" | "--output" | "--exr-type" | "--bit-depth" | "--quality"
+                | "--tonemap" | "--exposure" | "--white-point" | "--white-balance"
+        );
+        if !takes_value {
+            return Err(CliError::UnknownOption(arg));
+        }
+        let value = match inline.or_else(|| args.next()) {
+            Some(v) => v,
+            None => return Err(CliError::MissingValue(name)),
+        };
+        match name.as_str() {
+            "-W" | "--width" => opts.width = Some(parse_in_range(&name, &value, 1, MAX_IMAGE_SIDE as usize)?),
+            "-H" | "--height" => opts.height = Some(parse_in_range(&name, &value, 1, MAX_IMAGE_SIDE as usize)?),
+            "-s" | "--samples" => opts.samples = Some(parse_in_range(&name, &value, 1, MAX_SAMPLES)?),
+            "-d" | "--max-depth" => opts.max_depth = Some(parse_in_range(&name, &value, 1, MAX_PATH_DEPTH)?),
+            "--rr-depth" => opts.rr_depth = Some(parse_in_range(&name, &value, 0, MAX_PATH_DEPTH)?),
+            "--sampler" => {
+                opts.sampler = match SamplerKind::from_name(&value) {
+                    Some(kind) => kind,
+                    None => {
+                        return Err(invalid(
+                            &name,
+                            &value,
+                            "expected independent, stratified, halton or sobol",
+                        ))
+                    }
+                }
+            }
+            "--filter" => {
+                opts.filter = match FilterKind::from_name(&value) {
+                    Some(kind) => kind,
+                    None => {
+                        return Err(invalid(
+                            &name,
+                            &value,
+                            "expected box, tent, gaussian, mitchell or lanczos",
+                        ))
+                    }
+                }
+            }
+            "--filter-radius" => opts.filter_radius = Some(parse_in_range(&name, &value, 0.5, 16.0)?),
+            "--adaptive" => opts.adaptive = Some(parse_in_range(&name, &value, 1e-6, 1.0)?),
+            "--min-samples" => opts.min_samples = parse_in_range(&name, &value, 2, MAX_SAMPLES)?,
+            "--seed" => opts.seed = Some(parse_in_range(&name, &value, 0, u64::MAX)?),
+            "-j" | "--threads" => opts.threads = Some(parse_in_range(&name, &value, 1, 1024)?),
+            "--tile-size" => opts.tile_size = parse_in_range(&name, &value, 1, 1024)?,
+            "--tile-order" => {
+                opts.tile_order = match TileOrder::from_name(&value) {
+                    Some(order) => order,
+                    None => return Err(invalid(&name, &value, "expected scanline, spiral or hilbert")),
+                }
+            }
+            "--scene" => {
+                if !SCENES.contains(&value.as_str()) && !Path::new(&value).is_file() {
+                    return Err(invalid(
+                        &name,
+                        &value,
+                        &format!(
+                            "not a scene file or a built-in scene ({})",
+                            SCENES.join(", ")
+                        ),
+                    ));
+                }
+                opts.scene = value;
+            }
+            "--envmap" => {
+                if !Path::new(&value).is_file() {
+                    return Err(invalid(&name, &value, "no such file"));
+                }
+                opts.envmap = Some(PathBuf::from(value));
+            }
+            "-o" | "--output" => {
+                let path = PathBuf::from(&value);
+                if imageio::format_for_path(&path).is_none() {
+                    return Err(invalid(
+                        &name,
+                        &value,
+                        &format!("supported formats are {}", imageio::SUPPORTED_EXTENSIONS),
+                    ));
+                }
+                opts.output = path;
+            }
+            "--heatmap" | "--aovs" => {
+                let path = PathBuf::from(&value);
+                if imageio::format_for_path(&path).is_none() {
+                    return Err(invalid(
+                        &name,
+                        &value,
+                        &format!("supported formats are {}", imageio::SUPPORTED_EXTENSIONS),
+                    ));
+                }
+                if name == "--heatmap" {
+                    opts.heatmap = Some(path);
+                } else {
+                    opts.aovs = Some(path);
+                }
+            }
+            "--exr-type" => {
+                opts.exr_type = match value.as_str() {
+                    "half" => ExrPixelType::Half,
+                    "float" => ExrPixelType::Float,
+                    _ => return Err(invalid(&name, &value, "expected half or float")),
+                }
+            }
+            "--bit-depth" => {
+                opts.bit_depth = match value.as_str() {
+                    "8" => 8,
+                    "16" => 16,
+                    _ => return Err(invalid(&name, &value, "expected 8 or 16")),
+                }
+            }
+            "--quality" => opts.jpeg_quality = parse_in_range(&name, &value, 1, 100)?,
+            "--tonemap" => {
+                opts.tonemap.operator = match ToneOperator::from_name(&value) {
+                    Some(op) => op,
+                    None => {
+                        let names: Vec<&str> = ToneOperator::ALL.iter().map(|op| op.name()).collect();
+                        return Err(invalid(&name, &value, &format!("expected one of {}", names.join(", "))));
+                    }
+                }
+            }
+            "--exposure" => opts.tonemap.exposure = parse_in_range(&name, &value, -20.0, 20.0)?,
+            "--white-point" => opts.tonemap.white_point = parse_in_range(&name, &value, 0.01, 1e6)?,
+            "--white-balance" => {
+                opts.tonemap.white_balance = parse_in_range(&name, &value, 1667.0, 25000.0)?
+            }
+            _ => unreachable!(),
+        }
+    }
+    if opts.bit_depth == 16 {
+        match imageio::format_for_path(&opts.output) {
+            Some(f) if f.supports_16_bit() || f.is_hdr() => {}
+            _ => {
+                return Err(invalid(
+                    "--bit-depth",
+                    "16",
+                    &format!("{} can only be written at 8 bits", opts.output.display()),
+                ))
+            }
+        }
+    }
+    Ok(opts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, CliError> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+    fn invalid_option(args: &[&str]) -> String {
+        match parse(args) {
+            Err(CliError::InvalidValue { option, .. }) => option,
+            other => panic!("expected an invalid value, got {:?}", other.map(|o| o.command)),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_commands_and_options() {
+        assert_eq!(parse(&["draw"]).unwrap_err(), CliError::UnknownCommand("draw".to_string()));
+        assert_eq!(
+            parse(&["render", "--colour", "red"]).unwrap_err(),
+            CliError::UnknownOption("--colour".to_string())
+        );
+        assert_eq!(parse(&["-x"]).unwrap_err(), CliError::UnknownOption("-x".to_string()));
+        // a subcommand only counts in first place
+        assert_eq!(parse(&["-W", "10", "render"]).unwrap_err(), CliError::UnknownOption("render".to_string()));
+    }
+
+    #[test]
+    fn needs_a_value() {
+        assert_eq!(parse(&["render", "-W"]).unwrap_err(), CliError::MissingValue("-W".to_string()));
+        assert_eq!(parse(&["--samples"]).unwrap_err(), CliError::MissingValue("--samples".to_string()));
+    }
+
+    #[test]
+    fn checks_numbers_against_their_range() {
+        assert_eq!(invalid_option(&["-W", "0"]), "-W");
+        assert_eq!(invalid_option(&["--height", "16385"]), "--height");
+        assert_eq!(invalid_option(&["-s", "lots"]), "-s");
+        assert_eq!(invalid_option(&["-s", "-1"]), "-s");
+        assert_eq!(invalid_option(&["--exposure", "NaN"]), "--exposure");
+        assert_eq!(invalid_option(&["--white-point", "nan"]), "--white-point");
+        assert_eq!(invalid_option(&["--adaptive", "inf"]), "--adaptive");
+        assert_eq!(invalid_option(&["--quality", "101"]), "--quality");
+        let opts = parse(&["render", "-W", "16384", "--rr-depth", "0", "--exposure", "-20"]).unwrap();
+        assert_eq!(opts.command, Command::Render);
+        assert_eq!(opts.width, Some(16384));
+        assert_eq!(opts.rr_depth, Some(0));
+        assert_eq!(opts.tonemap.exposure, -20.0);
+    }
+
+    #[test]
+    fn accepts_inline_values() {
+        let opts = parse(&["--width=64", "--output=out.exr", "--exposure=-1.5", "-H", "32"]).unwrap();
+        assert_eq!(opts.width, Some(64));
+        assert_eq!(opts.height, Some(32));
+        assert_eq!(opts.output, PathBuf::from("out.exr"));
+        assert_eq!(opts.tonemap.exposure, -1.5);
+        // an empty inline value doesn't take the next argument instead
+        assert_eq!(invalid_option(&["--width=", "64"]), "--width");
+        // short options don't take = values
+        assert_eq!(parse(&["-W=64"]).unwrap_err(), CliError::UnknownOption("-W=64".to_string()));
+    }
+
+    #[test]
+    fn checks_output_extensions_and_bit_depth() {
+        assert_eq!(invalid_option(&["-o", "render.gif"]), "-o");
+        assert_eq!(invalid_option(&["-o", "render"]), "-o");
+        assert_eq!(invalid_option(&["--heatmap", "heat.txt"]), "--heatmap");
+        assert_eq!(invalid_option(&["--bit-depth", "12"]), "--bit-depth");
+        assert_eq!(invalid_option(&["--bit-depth", "16", "-o", "render.jpg"]), "--bit-depth");
+        assert_eq!(parse(&["--bit-depth", "16", "-o", "render.png"]).unwrap().bit_depth, 16);
+        assert_eq!(parse(&["-o", "render.exr", "--bit-depth=16"]).unwrap().bit_depth, 16);
+    }
+}
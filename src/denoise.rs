@@ -0,0 +1,251 @@
+use crate::aov::AovPixel;
+use crate::render::Framebuffer;
+use crate::vectors::Vector3;
+use crate::vectors::Vector3 as Color;
+use rayon::prelude::*;
+
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Edge-avoiding a-trous wavelet filter (Dammertz et al.). Every pass blurs with a 5x5 B3 spline
+// whose taps are spread 2^pass pixels apart, and turns down taps whose normal, depth, albedo or
+// color are too far off the center pixel's, so the noise goes and the edges stay. The lighting is
+// filtered with the albedo divided out, which keeps textures from being smeared along with it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Denoiser {
+    pub iterations: u32,
+    pub color_sigma: f64,  // on c / (1 + c) so fireflies can't dominate, halved every pass
+    pub normal_power: f64, // a tap's weight goes with (n . n')^normal_power
+    pub depth_sigma: f64,  // relative depth difference allowed per pixel of distance
+    pub albedo_sigma: f64,
+}
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser {
+            iterations: 5,
+            color_sigma: 0.5,
+            normal_power: 64.0,
+            depth_sigma: 0.05,
+            albedo_sigma: 0.1,
+        }
+    }
+}
+
+// What the filter steers by, worked out once per pixel
+struct Guide {
+    hit: bool,
+    normal: Vector3<f64>,
+    depth: f64,
+    albedo: Color<f64>,
+}
+
+fn compress(c: &Color<f64>) -> Color<f64> {
+    Color::from_tuple((c.x / (1.0 + c.x), c.y / (1.0 + c.y), c.z / (1.0 + c.z)))
+}
+
+// Per channel median of the 3x3 neighbourhood, so the first pass compares colors without single
+// pixel fireflies getting in the way. A firefly then looks like its surroundings and is blurred
+// out with them instead of being kept as an edge
+fn median_3x3(colors: &[Color<f64>], width: usize, height: usize) -> Vec<Color<f64>> {
+    let mut out = Vec::with_capacity(colors.len());
+    let mut window: [Vec<f64>; 3] = [vec![], vec![], vec![]];
+    for y in 0..height {
+        for x in 0..width {
+            for w in window.iter_mut() {
+                w.clear();
+            }
+            for qy in y.saturating_sub(1)..(y + 2).min(height) {
+                for qx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let c = colors[qy * width + qx];
+                    window[0].push(c.x);
+                    window[1].push(c.y);
+                    window[2].push(c.z);
+                }
+            }
+            let mut median = [0.0; 3];
+            for (m, w) in median.iter_mut().zip(window.iter_mut()) {
+                w.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                *m = w[w.len() / 2];
+            }
+            out.push(Color::from_tuple((median[0], median[1], median[2])));
+        }
+    }
+    out
+}
+
+// Channels with next to no albedo are filtered as they are
+fn demodulate(c: f64, albedo: f64) -> f64 {
+    if albedo > 1e-3 {
+        c / albedo
+    } else {
+        c
+    }
+}
+fn remodulate(c: f64, albedo: f64) -> f64 {
+    if albedo > 1e-3 {
+        c * albedo
+    } else {
+        c
+    }
+}
+
+impl Denoiser {
+    // aovs has to line up with fb's pixels, as a Film recording AOVs has them
+    pub fn denoise(&self, fb: &Framebuffer, aovs: &[AovPixel]) -> Framebuffer {
+        assert_eq!(aovs.len(), fb.pixels.len(), "denoising needs the AOVs of every pixel");
+        let width = fb.width;
+        let guides: Vec<Guide> = aovs
+            .iter()
+            .map(|a| Guide {
+                hit: a.hits > 0,
+                normal: if a.hits > 0 { a.normal().normalized() } else { Vector3::zero() },
+                depth: a.depth(),
+                albedo: a.albedo(),
+            })
+            .collect();
+        let mut light: Vec<Color<f64>> = fb
+            .pixels
+            .iter()
+            .zip(guides.iter())
+            .map(|(c, g)| {
+                Color::from_tuple((
+                    demodulate(c.x, g.albedo.x),
+                    demodulate(c.y, g.albedo.y),
+                    demodulate(c.z, g.albedo.z),
+                ))
+            })
+            .collect();
+        let mut next = vec![Color::zero(); light.len()];
+        for pass in 0..self.iterations {
+            let step = 1usize << pass;
+            let sigma = self.color_sigma * 0.5f64.powi(pass as i32);
+            let compressed: Vec<Color<f64>> = if pass == 0 {
+                median_3x3(&light, width, fb.height).iter().map(compress).collect()
+            } else {
+                light.iter().map(compress).collect()
+            };
+            next.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+                for (x, out) in row.iter_mut().enumerate() {
+                    *out = self.filter_pixel(fb, &guides, &light, &compressed, x, y, step, sigma);
+                }
+            });
+            std::mem::swap(&mut light, &mut next);
+        }
+        Framebuffer {
+            width: fb.width,
+            height: fb.height,
+            pixels: light
+                .iter()
+                .zip(guides.iter())
+                .map(|(c, g)| {
+                    Color::from_tuple((
+                        remodulate(c.x, g.albedo.x),
+                        remodulate(c.y, g.albedo.y),
+                        remodulate(c.z, g.albedo.z),
+                    ))
+                })
+                .collect(),
+        }
+    }
+
+    fn filter_pixel(
+        &self,
+        fb: &Framebuffer,
+        guides: &[Guide],
+        light: &[Color<f64>],
+        compressed: &[Color<f64>],
+        x: usize,
+        y: usize,
+        step: usize,
+        sigma: f64,
+    ) -> Color<f64> {
+        let p = y * fb.width + x;
+        let g = &guides[p];
+        let (mut sum, mut total) = (Color::zero(), 0.0);
+        for (j, ky) in KERNEL.iter().enumerate() {
+            let qy = y as i64 + (j as i64 - 2) * step as i64;
+            if qy < 0 || qy >= fb.height as i64 {
+                continue;
+            }
+            for (i, kx) in KERNEL.iter().enumerate() {
+                let qx = x as i64 + (i as i64 - 2) * step as i64;
+                if qx < 0 || qx >= fb.width as i64 {
+                    continue;
+                }
+                let q = qy as usize * fb.width + qx as usize;
+                let h = &guides[q];
+                // anything hit never mixes with the background
+                if g.hit != h.hit {
+                    continue;
+                }
+                let mut w = kx * ky;
+                if g.hit {
+                    let dist = step as f64 * (((i as f64 - 2.0).powi(2) + (j as f64 - 2.0).powi(2)).sqrt());
+                    w *= g.normal.dot(&h.normal).max(0.0).powf(self.normal_power);
+                    if dist > 0.0 {
+                        w *= (-(g.depth - h.depth).abs() / (self.depth_sigma * g.depth * dist)).exp();
+                    }
+                }
+                w *= (-(g.albedo - h.albedo).sqrmagnitude() / (self.albedo_sigma * self.albedo_sigma)).exp();
+                w *= (-(compressed[p] - compressed[q]).sqrmagnitude() / (sigma * sigma)).exp();
+                sum = sum + &light[q] * w;
+                total += w;
+            }
+        }
+        // the center tap always counts, so total is never 0
+        &sum * (1.0 / total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::AovSample;
+    use crate::rng::Pcg32;
+    use rand::Rng;
+
+    // A wall split down the middle into two colors facing different ways, with noisy lighting
+    #[test]
+    fn smooths_noise_but_keeps_edges() {
+        let (width, height) = (32, 16);
+        let mut rng = Pcg32::new(7, 0);
+        let mut fb = Framebuffer::new(width, height);
+        let mut aovs = vec![];
+        for i in 0..width * height {
+            let left = i % width < width / 2;
+            let albedo = if left { Color::from_tuple((0.8, 0.2, 0.2)) } else { Color::from_tuple((0.2, 0.2, 0.8)) };
+            let normal = if left { Vector3::from_tuple((1.0, 0.0, 1.0)) } else { Vector3::from_tuple((-1.0, 0.0, 1.0)) };
+            let mut a = AovPixel::new();
+            a.add(&AovSample {
+                depth: 5.0,
+                normal: normal.normalized(),
+                position: Vector3::zero(),
+                albedo: albedo,
+                ids: Some((0, left as u32)),
+            });
+            aovs.push(a);
+            fb.pixels[i] = &albedo * rng.gen_range(0.5, 1.5);
+        }
+        let out = Denoiser::default().denoise(&fb, &aovs);
+        // relative spread of the red channel on each half, and the halves' means
+        let stats = |fb: &Framebuffer, left: bool| {
+            let v: Vec<f64> = (0..width * height)
+                .filter(|i| (i % width < width / 2) == left)
+                .map(|i| fb.pixels[i].x)
+                .collect();
+            let mean = v.iter().sum::<f64>() / v.len() as f64;
+            let var = v.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / v.len() as f64;
+            (mean, var.sqrt() / mean)
+        };
+        for &left in [true, false].iter() {
+            let (before_mean, before_noise) = stats(&fb, left);
+            let (after_mean, after_noise) = stats(&out, left);
+            assert!(after_noise < before_noise / 4.0, "{} {}", before_noise, after_noise);
+            assert!((after_mean - before_mean).abs() < 0.05 * before_mean);
+        }
+        // nothing from the blue side bleeds over the edge
+        for y in 0..height {
+            let edge = out.get(width / 2 - 1, y);
+            assert!(edge.z < 0.3 && edge.x > 0.6, "{}", edge);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+// Pixel reconstruction filters. A sample is splatted into every pixel whose center is within the
+// filter's radius, weighted by the filter at the offset from that center (in pixels)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+impl FilterKind {
+    pub const ALL: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+        FilterKind::Lanczos,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian => "gaussian",
+            FilterKind::Mitchell => "mitchell",
+            FilterKind::Lanczos => "lanczos",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<FilterKind> {
+        FilterKind::ALL.iter().cloned().find(|k| k.name() == name)
+    }
+    pub fn default_radius(self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64,
+}
+impl Default for Filter {
+    // one sample lands in exactly one pixel, the plain per pixel average
+    fn default() -> Filter {
+        Filter::new(FilterKind::Box)
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    let px = std::f64::consts::PI * x;
+    px.sin() / px
+}
+
+// Mitchell-Netravali with B = C = 1/3, x in [0, 2]
+fn mitchell(x: f64) -> f64 {
+    let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+    let x = x.abs();
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b))
+            / 6.0
+    } else if x < 2.0 {
+        ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        0.0
+    }
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind) -> Filter {
+        Filter {
+            kind: kind,
+            radius: kind.default_radius(),
+        }
+    }
+    // How many pixels past its own a sample can reach
+    pub fn pixel_reach(&self) -> usize {
+        (self.radius - 0.5).max(0.0).ceil() as usize
+    }
+    pub fn eval_1d(&self, x: f64) -> f64 {
+        let r = self.radius;
+        match self.kind {
+            // half open, so a sample on a pixel edge counts for one pixel only
+            FilterKind::Box => (x >= -r && x < r) as i32 as f64,
+            FilterKind::Tent => (1.0 - x.abs() / r).max(0.0),
+            FilterKind::Gaussian => {
+                // sigma of a third of the radius, shifted down so it reaches 0 at the radius
+                let g = |x: f64| (-4.5 * x * x / (r * r)).exp();
+                (g(x) - g(r)).max(0.0)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / r),
+            FilterKind::Lanczos => {
+                if x.abs() >= r {
+                    0.0
+                } else {
+                    sinc(x) * sinc(x / r)
+                }
+            }
+        }
+    }
+    // Separable, dx and dy are the sample position minus the pixel center
+    pub fn eval(&self, dx: f64, dy: f64) -> f64 {
+        self.eval_1d(dx) * self.eval_1d(dy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_peak_in_the_middle_and_vanish_at_the_radius() {
+        for kind in FilterKind::ALL.iter() {
+            let f = Filter::new(*kind);
+            let peak = f.eval(0.0, 0.0);
+            assert!(peak > 0.0, "{:?}", kind);
+            assert_eq!(f.eval(f.radius + 0.01, 0.0), 0.0, "{:?}", kind);
+            assert_eq!(f.eval(0.0, -f.radius - 0.01), 0.0, "{:?}", kind);
+            let mut x = 0.0;
+            while x < f.radius {
+                assert!(f.eval(x, 0.0) <= peak + 1e-12, "{:?} at {}", kind, x);
+                x += 0.05;
+            }
+        }
+        let box_filter = Filter::default();
+        assert_eq!(box_filter.pixel_reach(), 0);
+        assert_eq!(box_filter.eval(-0.5, 0.0), 1.0);
+        assert_eq!(box_filter.eval(0.5, 0.0), 0.0);
+        assert_eq!(Filter::new(FilterKind::Mitchell).pixel_reach(), 2);
+    }
+}
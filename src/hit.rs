@@ -12,7 +12,9 @@ pub struct HitInfo<'a> {
     pub p: Vector3<f64>,
     pub normal: Vector3<f64>,
     pub front_face: bool,
+    pub uv: Vector3<f64>,
     pub mat: &'a Arc<dyn Material + Send + Sync>, //SHARED PTR IN TUTORIAL -- MAY NEED TO BE ARC, OR &, OR &MUT
+    pub object_id: u32, // which World object, every triangle of a mesh shares its mesh's
 }
 
 pub trait Hittable {
@@ -64,27 +66,91 @@ pub struct Tri {
     pub v2: Vert,
     pub mat: Arc<dyn Material + Send + Sync>,
     pub bbox: Bounds,
+    pub id: u32,
+    // only hit from the side the winding (v0, v1, v2) faces, counter-clockwise seen from the front
+    pub cull_backfaces: bool,
 }
 impl Tri {
     fn center(&self) -> Vector3<f64> {
         return (self.v0.P + self.v1.P + self.v2.P) / 3.0;
     }
-    fn getBaryCentric(&self, p: Vector3<f64>) -> Vector3<f64> {
-        let v0v1 = self.v1.P - self.v0.P;
-        let v0v2 = self.v2.P - self.v0.P;
-        // no need to normalize
-        let N = v0v1.cross(&v0v2);
-        let edge0 = self.v1.P - self.v0.P;
-        let edge1 = self.v2.P - self.v1.P;
-        let edge2 = self.v0.P - self.v2.P;
-        // no need to normalize
-        // let N = edge0.cross(&edge2);
-        let denom = N.dot(&N); // N
-        let u = N.dot(&edge1.cross(&(p - self.v1.P))) / denom;
-        let v = N.dot(&edge2.cross(&(p - self.v2.P))) / denom;
-        let w = 1.0 - u - v;
-        return Vector3 { x: u, y: v, z: w };
+}
+
+// Where a ray crosses a triangle: the distance, the barycentrics u and v of the second and third
+// corners, which side it came from and the unit face normal of the winding
+pub struct TriHit {
+    pub t: f64,
+    pub u: f64,
+    pub v: f64,
+    pub front_face: bool,
+    pub geometric: Vector3<f64>,
+}
+impl TriHit {
+    // Per corner attributes blended at the hit
+    pub fn interpolate(&self, a0: &Vector3<f64>, a1: &Vector3<f64>, a2: &Vector3<f64>) -> Vector3<f64> {
+        ((1.0 - self.u - self.v) * *a0) + (self.u * *a1) + (self.v * *a2)
+    }
+    // Interpolated vertex normals, kept on the geometric normal's side and turned towards the ray.
+    // Triangles without any just use the face's
+    pub fn shading_normal(&self, n0: &Vector3<f64>, n1: &Vector3<f64>, n2: &Vector3<f64>) -> Vector3<f64> {
+        let mut shading = self.interpolate(n0, n1, n2);
+        if shading.sqrmagnitude() < 1e-12 {
+            shading = self.geometric;
+        } else if shading.dot(&self.geometric) < 0.0 {
+            shading = &shading * -1.0;
+        }
+        let shading = shading.normalized();
+        if self.front_face {
+            shading
+        } else {
+            &shading * -1.0
+        }
+    }
+}
+
+// Moller-Trumbore: solves origin + t * dir = p0 + u * e1 + v * e2 straight away, so the
+// barycentrics come out of the same few dot and cross products. Edges and corners count as inside
+pub fn hit_triangle(
+    p0: &Vector3<f64>,
+    p1: &Vector3<f64>,
+    p2: &Vector3<f64>,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+    cull_backfaces: bool,
+) -> Option<TriHit> {
+    let e1 = *p1 - *p0;
+    let e2 = *p2 - *p0;
+    let pvec = r.dir.cross(&e2);
+    // -dir . (e1 x e2), so positive when the ray comes at the front. It scales with the edge and
+    // direction lengths, so parallel is judged against those (squared, to skip the square roots)
+    let det = e1.dot(&pvec);
+    let scale2 = e1.sqrmagnitude() * e2.sqrmagnitude() * r.dir.sqrmagnitude();
+    if det * det <= 1e-24 * scale2 || (cull_backfaces && det < 0.0) {
+        return None; // parallel to the plane, degenerate, or the back of a culled face
+    }
+    let inv_det = 1.0 / det;
+    let tvec = r.origin - *p0;
+    let u = tvec.dot(&pvec) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+    let qvec = tvec.cross(&e1);
+    let v = r.dir.dot(&qvec) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
     }
+    let t = e2.dot(&qvec) * inv_det;
+    if t <= t_min || t >= t_max {
+        return None;
+    }
+    Some(TriHit {
+        t: t,
+        u: u,
+        v: v,
+        front_face: det > 0.0,
+        geometric: e1.cross(&e2).normalized(),
+    })
 }
 
 impl Hittable for Tri {
@@ -92,86 +158,27 @@ impl Hittable for Tri {
         return &self.bbox;
     }
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitInfo> {
-        let v0v1 = self.v1.P - self.v0.P;
-        let v0v2 = self.v2.P - self.v0.P;
-        let normal = v0v1.cross(&v0v2).normalized(); // Possibly inverted
-        let D = normal.dot(&self.v0.P);
-        let t = (-1.0 * normal.dot(&r.origin) + D) / (normal.dot(&r.dir)); // WARNING ABOUT INVERTED HERE IN SCRATCHPIXEL
-        if normal.dot(&r.dir).abs() < 0.00000001 {
-            // parallel
-            return None;
-        }
-        let pHit = r.at(t);
-        //Backface cull -- can we have two sided?
-        if t < 0.0 {
-            return None;
-        }
-
-        // // Starting left test
-
-        //edge 0
-        let v0v1 = self.v1.P - self.v0.P;
-        let vp0 = pHit - self.v0.P;
-        let C = v0v1.cross(&vp0);
-        if normal.dot(&C) < 0.0 {
-            return None;
-        };
-
-        //edge 1
-        let v0v1 = self.v2.P - self.v1.P;
-        let vp1 = pHit - self.v1.P;
-        let C = v0v1.cross(&vp1);
-        if normal.dot(&C) < 0.0 {
-            return None;
-        };
-        //edge 2
-        let v0v2 = self.v0.P - self.v2.P;
-        let vp2 = pHit - self.v2.P;
-        let C = v0v2.cross(&vp2);
-        if normal.dot(&C) < 0.0 {
-            return None;
-        };
-        let bary = self.getBaryCentric(pHit);
-
-        let temp = t;
-        let outward_normal = (bary.x * self.v0.N) + (bary.y * self.v1.N) + (bary.z * self.v2.N);
-        let f_face = r.dir.dot(&outward_normal) < 0.0;
-        if outward_normal.dot(&r.dir).abs() < 0.00000001 {
-            // parallel
-            return None;
-        }
-        if t < t_max && temp > t_min {
-            return Some(HitInfo {
-                p: pHit,
-                t: temp, //think this should be like, distance from origin to t, instead
-                normal: match f_face {
-                    true => outward_normal,
-                    false => &outward_normal * -1.0,
-                },
-                front_face: !f_face,
-                mat: &self.mat,
-            });
-        } else {
-            return None;
-        }
-
-        // let projVec = r.dir.cross(&v0v2);
-        // let determinant = edge0.dot(&projVec);
+        let hit = hit_triangle(&self.v0.P, &self.v1.P, &self.v2.P, r, t_min, t_max, self.cull_backfaces)?;
+        Some(HitInfo {
+            t: hit.t,
+            p: r.at(hit.t),
+            normal: hit.shading_normal(&self.v0.N, &self.v1.N, &self.v2.N),
+            front_face: hit.front_face,
+            uv: hit.interpolate(&self.v0.UV, &self.v1.UV, &self.v2.UV),
+            mat: &self.mat,
+            object_id: self.id,
+        })
+    }
+}
 
-        // let projected = r.at((r.origin - self.projectToTri(r.dir)).sqrmagnitude());
-        // let oc = projected - (r.origin * &r.dir);
-        // let a = r.dir.sqrmagnitude();
-        // let half_b = oc.dot(&r.dir);
-        // // assert_eq!(r.at((r.origin - projected).sqrmagnitude()), projected);
-        // match &self.triContains(projected) {
-        //     true => {
-        //         let discriminant = (half_b * half_b) * (a * oc.sqrmagnitude());
-        // // So the idea is gonna be....
-        //project any point along the ray (let's say origin) onto the tris plane
-        // Check if that hit point is the same direciton as ray
-        // Check if that hit point is intersecting tri
-        // Check if that hit point is within range
-        // If so, hit!! returning the tris normal, and any vert info thru barycentric lookup
+// u around the equator from -x, v from the south pole up
+pub fn sphere_uv(n: &Vector3<f64>) -> Vector3<f64> {
+    let theta = (-n.y).acos();
+    let phi = (-n.z).atan2(n.x) + std::f64::consts::PI;
+    Vector3 {
+        x: phi / (2.0 * std::f64::consts::PI),
+        y: theta / std::f64::consts::PI,
+        z: 0.0,
     }
 }
 
@@ -180,6 +187,7 @@ pub struct Sphere {
     pub radius: f64,
     pub mat: Arc<dyn Material + Send + Sync>, //SHARED PTR IN TUTORIAL -- MAY NEED TO BE ARC, OR &, OR &MUT
     pub bbox: Bounds,
+    pub id: u32,
 }
 impl Hittable for Sphere {
     fn get_bounds(&self) -> &Bounds {
@@ -209,7 +217,9 @@ impl Hittable for Sphere {
                         true => outward_normal,
                         false => &outward_normal * -1.0,
                     },
+                    uv: sphere_uv(&outward_normal),
                     mat: &self.mat,
+                    object_id: self.id,
                 });
             };
             let temp = (-half_b + root) / a;
@@ -225,10 +235,121 @@ impl Hittable for Sphere {
                         true => outward_normal,
                         false => &outward_normal * -1.0,
                     },
+                    uv: sphere_uv(&outward_normal),
                     mat: &self.mat,
+                    object_id: self.id,
                 });
             }
         }
         return None;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambert;
+
+    // The unit right triangle in the z = 0 plane, wound to face +z, with uv = xy
+    fn unit_tri(cull_backfaces: bool) -> Tri {
+        let vert = |x: f64, y: f64| Vert {
+            P: Vector3::from_tuple((x, y, 0.0)),
+            N: Vector3::from_tuple((0.0, 0.0, 1.0)),
+            UV: Vector3::from_tuple((x, y, 0.0)),
+        };
+        let mut bbox = Bounds::new();
+        bbox.fitPoints(vec![Vector3::zero(), Vector3::right(), Vector3::up()]);
+        Tri {
+            v0: vert(0.0, 0.0),
+            v1: vert(1.0, 0.0),
+            v2: vert(0.0, 1.0),
+            mat: Arc::new(Lambert { albedo: Color::one() }),
+            bbox: bbox,
+            id: 3,
+            cull_backfaces: cull_backfaces,
+        }
+    }
+    fn down_at(x: f64, y: f64) -> Ray {
+        Ray::new(Vector3::from_tuple((x, y, 2.0)), Vector3::from_tuple((0.0, 0.0, -1.0)))
+    }
+
+    #[test]
+    fn hits_report_t_barycentrics_and_facing() {
+        let tri = unit_tri(false);
+        let hit = tri.hit(&down_at(0.25, 0.5), 0.001, f64::INFINITY).unwrap();
+        assert_eq!(hit.t, 2.0);
+        assert_eq!(hit.p, Vector3::from_tuple((0.25, 0.5, 0.0)));
+        assert_eq!(hit.uv, Vector3::from_tuple((0.25, 0.5, 0.0)));
+        assert!(hit.front_face);
+        // the vertex normals, turned towards the ray
+        assert_eq!(hit.normal, Vector3::from_tuple((0.0, 0.0, 1.0)));
+        assert_eq!(hit.object_id, 3);
+
+        let from_below = Ray::new(Vector3::from_tuple((0.25, 0.5, -2.0)), Vector3::from_tuple((0.0, 0.0, 1.0)));
+        let hit = tri.hit(&from_below, 0.001, f64::INFINITY).unwrap();
+        assert!(!hit.front_face);
+        assert_eq!(hit.normal, Vector3::from_tuple((0.0, 0.0, -1.0)));
+        assert!(unit_tri(true).hit(&from_below, 0.001, f64::INFINITY).is_none());
+        assert!(unit_tri(true).hit(&down_at(0.25, 0.5), 0.001, f64::INFINITY).is_some());
+
+        let sideways = Ray::new(Vector3::from_tuple((-1.0, 0.25, 0.0)), Vector3::right());
+        assert!(tri.hit(&sideways, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn parallel_test_is_relative_to_the_triangle_size() {
+        let down = Vector3::from_tuple((0.0, 0.0, -1.0));
+        for &size in [1e-7, 1.0, 1e7].iter() {
+            let (p0, p1, p2) = (Vector3::zero(), &Vector3::right() * size, &Vector3::up() * size);
+            let r = Ray::new(Vector3::from_tuple((0.25 * size, 0.5 * size, size)), down);
+            let hit = hit_triangle(&p0, &p1, &p2, &r, 0.0, f64::INFINITY, false).unwrap();
+            assert!((hit.t - size).abs() <= 1e-9 * size);
+            assert!((hit.u - 0.25).abs() < 1e-9 && (hit.v - 0.5).abs() < 1e-9);
+            // a short direction vector mustn't make it look parallel either
+            let slow = Ray::new(r.origin, &down * 1e-6);
+            assert!(hit_triangle(&p0, &p1, &p2, &slow, 0.0, f64::INFINITY, false).is_some());
+            let grazing = Ray::new(Vector3::from_tuple((-size, 0.25 * size, 0.0)), Vector3::right());
+            assert!(hit_triangle(&p0, &p1, &p2, &grazing, 0.0, f64::INFINITY, false).is_none());
+            // collapsed to a line
+            let r = Ray::new(Vector3::from_tuple((0.5 * size, 0.0, size)), down);
+            assert!(hit_triangle(&p0, &p1, &(&p1 * 2.0), &r, 0.0, f64::INFINITY, false).is_none());
+        }
+    }
+
+    #[test]
+    fn missing_vertex_normals_fall_back_to_the_face() {
+        let mut tri = unit_tri(false);
+        for v in [&mut tri.v0, &mut tri.v1, &mut tri.v2].iter_mut() {
+            v.N = Vector3::zero();
+        }
+        let hit = tri.hit(&down_at(0.25, 0.5), 0.001, f64::INFINITY).unwrap();
+        assert_eq!(hit.normal, Vector3::from_tuple((0.0, 0.0, 1.0)));
+        let from_below = Ray::new(Vector3::from_tuple((0.25, 0.5, -2.0)), Vector3::from_tuple((0.0, 0.0, 1.0)));
+        let hit = tri.hit(&from_below, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(hit.normal, Vector3::from_tuple((0.0, 0.0, -1.0)));
+    }
+
+    #[test]
+    fn edges_and_corners_count_as_inside() {
+        let tri = unit_tri(false);
+        for &(x, y) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.5, 0.0), (0.0, 0.5), (0.5, 0.5)].iter() {
+            assert!(tri.hit(&down_at(x, y), 0.001, f64::INFINITY).is_some(), "{} {}", x, y);
+        }
+        for &(x, y) in [(0.5, -1e-9), (-1e-9, 0.5), (0.5 + 1e-9, 0.5), (1.0 + 1e-9, 0.0)].iter() {
+            assert!(tri.hit(&down_at(x, y), 0.001, f64::INFINITY).is_none(), "{} {}", x, y);
+        }
+    }
+
+    #[test]
+    fn respects_the_t_range() {
+        let tri = unit_tri(false);
+        let r = down_at(0.25, 0.25);
+        assert!(tri.hit(&r, 0.001, 2.0).is_none());
+        assert!(tri.hit(&r, 0.001, 2.001).is_some());
+        assert!(tri.hit(&r, 2.0, 10.0).is_none());
+        assert!(tri.hit(&r, 1.999, 10.0).is_some());
+        // behind the origin
+        let away = Ray::new(Vector3::from_tuple((0.25, 0.25, 2.0)), Vector3::from_tuple((0.0, 0.0, 1.0)));
+        assert!(tri.hit(&away, 0.001, f64::INFINITY).is_none());
+    }
+}
@@ -0,0 +1,763 @@
+use crate::jpeg;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+
+// Display referred RGB, already sRGB encoded, row 0 at the top. Samples run up to 255 for
+// 8 bit images and 65535 for 16 bit ones
+pub struct LdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub bit_depth: u8,
+    pub pixels: Vec<[u16; 3]>,
+}
+impl LdrImage {
+    fn rgb8(&self) -> impl Iterator<Item = [u8; 3]> + '_ {
+        let shift = self.bit_depth - 8;
+        self.pixels
+            .iter()
+            .take(self.width * self.height)
+            .map(move |p| [(p[0] >> shift) as u8, (p[1] >> shift) as u8, (p[2] >> shift) as u8])
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+    Jpeg,
+    Tga,
+    Exr,
+    Pfm,
+}
+impl ImageFormat {
+    // Written straight from the linear float framebuffer instead of the display buffer
+    pub fn is_hdr(self) -> bool {
+        self == ImageFormat::Exr || self == ImageFormat::Pfm
+    }
+    pub fn supports_16_bit(self) -> bool {
+        self == ImageFormat::Png || self == ImageFormat::Ppm
+    }
+}
+pub const SUPPORTED_EXTENSIONS: &str = ".ppm, .png, .jpg, .tga, .exr, .pfm";
+
+pub fn format_for_path(path: &Path) -> Option<ImageFormat> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("ppm") => Some(ImageFormat::Ppm),
+        Some("png") => Some(ImageFormat::Png),
+        Some("jpg") | Some("jpeg") => Some(ImageFormat::Jpeg),
+        Some("tga") => Some(ImageFormat::Tga),
+        Some("exr") => Some(ImageFormat::Exr),
+        Some("pfm") => Some(ImageFormat::Pfm),
+        _ => None,
+    }
+}
+
+fn unsupported_output(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "don't know how to write '{}', use one of {}",
+            path.display(),
+            SUPPORTED_EXTENSIONS
+        ),
+    )
+}
+
+// Picks a display writer from the file extension. metadata is (key, value) text, stored however
+// the format allows: tEXt chunks, JPEG comments, the TGA image id or PPM comments
+pub fn save_ldr(
+    path: &Path,
+    image: &LdrImage,
+    metadata: &[(String, String)],
+    jpeg_quality: u8,
+) -> io::Result<()> {
+    let format = match format_for_path(path) {
+        Some(f) if !f.is_hdr() => f,
+        _ => return Err(unsupported_output(path)),
+    };
+    if image.bit_depth != 8 && !format.supports_16_bit() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: 16 bit output needs a .png or .ppm file", path.display()),
+        ));
+    }
+    match format {
+        ImageFormat::Ppm => write_ppm(path, image, metadata),
+        ImageFormat::Png => write_png(path, image, metadata),
+        ImageFormat::Jpeg => write_jpeg(path, image, metadata, jpeg_quality),
+        _ => write_tga(path, image, metadata),
+    }
+}
+
+// Picks a float writer from the file extension. pixels are linear RGB, row 0 at the top
+pub fn save_hdr(
+    path: &Path,
+    width: usize,
+    height: usize,
+    pixels: &[[f32; 3]],
+    exr_type: ExrPixelType,
+) -> io::Result<()> {
+    match format_for_path(path) {
+        Some(ImageFormat::Exr) => write_exr(path, width, height, &rgb_channels(pixels), exr_type),
+        Some(ImageFormat::Pfm) => write_pfm(path, width, height, pixels),
+        _ => Err(unsupported_output(path)),
+    }
+}
+
+pub fn write_ppm(path: &Path, image: &LdrImage, metadata: &[(String, String)]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"P6\n")?;
+    for (k, v) in metadata {
+        file.write_all(format!("# {}: {}\n", k, v.replace('\n', " ")).as_bytes())?;
+    }
+    let max = if image.bit_depth == 8 { 255 } else { 65535 };
+    file.write_all(format!("{} {}\n{}\n", image.width, image.height, max).as_bytes())?;
+    for p in image.pixels.iter().take(image.width * image.height) {
+        for c in p.iter() {
+            if image.bit_depth == 8 {
+                file.write_all(&[*c as u8])?;
+            } else {
+                file.write_all(&c.to_be_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
+
+pub fn write_png(path: &Path, image: &LdrImage, metadata: &[(String, String)]) -> io::Result<()> {
+    let (width, height) = (image.width, image.height);
+    let bytes_per_sample = if image.bit_depth == 8 { 1 } else { 2 };
+    // Filter byte (0 = none) at the start of every scanline, then RGB8 or big endian RGB16
+    let mut raw: Vec<u8> = Vec::with_capacity(height * (1 + width * 3 * bytes_per_sample));
+    for row in image.pixels.chunks(width).take(height) {
+        raw.push(0);
+        for p in row {
+            for c in p.iter() {
+                if image.bit_depth == 8 {
+                    raw.push(*c as u8);
+                } else {
+                    raw.extend_from_slice(&c.to_be_bytes());
+                }
+            }
+        }
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[image.bit_depth, 2, 0, 0, 0]); // truecolor, deflate, adaptive filter, no interlace
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'])?;
+    write_png_chunk(&mut file, b"IHDR", &ihdr)?;
+    write_png_chunk(&mut file, b"sRGB", &[0])?; // perceptual
+    for (k, v) in metadata {
+        // keywords are 1-79 bytes of Latin-1, plain ASCII keeps that simple
+        let key: String = k
+            .chars()
+            .filter(|c| c.is_ascii_graphic() || *c == ' ')
+            .take(79)
+            .collect();
+        if key.is_empty() {
+            continue;
+        }
+        let mut text = key.into_bytes();
+        text.push(0);
+        text.extend(v.chars().map(|c| if c.is_ascii() { c as u8 } else { b'?' }));
+        write_png_chunk(&mut file, b"tEXt", &text)?;
+    }
+    write_png_chunk(&mut file, b"IDAT", &zlib_stored(&raw))?;
+    write_png_chunk(&mut file, b"IEND", &[])?;
+    file.flush()
+}
+
+// TGA and JPEG headers only have 16 bits for each side
+fn check_16_bit_size(path: &Path, image: &LdrImage) -> io::Result<()> {
+    if image.width > u16::MAX as usize || image.height > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}: {}x{} is too big, the format allows at most {} pixels a side",
+                path.display(),
+                image.width,
+                image.height,
+                u16::MAX
+            ),
+        ));
+    }
+    Ok(())
+}
+
+pub fn write_jpeg(
+    path: &Path,
+    image: &LdrImage,
+    metadata: &[(String, String)],
+    quality: u8,
+) -> io::Result<()> {
+    check_16_bit_size(path, image)?;
+    let rgb: Vec<[u8; 3]> = image.rgb8().collect();
+    let comments: Vec<String> = metadata.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+    let bytes = jpeg::encode(image.width, image.height, &rgb, quality, &comments)?;
+    let mut file = File::create(path)?;
+    file.write_all(&bytes)
+}
+
+// Uncompressed truecolor, top-left origin. The metadata goes into the (up to 255 byte) image id
+pub fn write_tga(path: &Path, image: &LdrImage, metadata: &[(String, String)]) -> io::Result<()> {
+    check_16_bit_size(path, image)?;
+    let id: Vec<u8> = metadata
+        .iter()
+        .map(|(k, v)| format!("{}: {}", k, v))
+        .collect::<Vec<String>>()
+        .join("; ")
+        .bytes()
+        .take(255)
+        .collect();
+    let mut header = vec![id.len() as u8, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    header.extend_from_slice(&(image.width as u16).to_le_bytes());
+    header.extend_from_slice(&(image.height as u16).to_le_bytes());
+    header.extend_from_slice(&[24, 0x20]);
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&header)?;
+    file.write_all(&id)?;
+    for p in image.rgb8() {
+        file.write_all(&[p[2], p[1], p[0]])?;
+    }
+    file.flush()
+}
+
+fn write_png_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    let mut crc = crc32_update(0xffff_ffff, kind);
+    crc = crc32_update(crc, data);
+    w.write_all(&(crc ^ 0xffff_ffff).to_be_bytes())
+}
+
+// zlib stream made of uncompressed deflate blocks -- no compression, but no extra deps either
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 65535 * 5 + 16);
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExrPixelType {
+    Half,
+    Float,
+}
+
+// One named plane of a multi-channel image, row 0 at the top
+pub struct ImageChannel {
+    pub name: String,
+    pub data: Vec<f32>,
+}
+
+pub fn rgb_channels(pixels: &[[f32; 3]]) -> Vec<ImageChannel> {
+    ["R", "G", "B"]
+        .iter()
+        .enumerate()
+        .map(|(c, name)| ImageChannel {
+            name: name.to_string(),
+            data: pixels.iter().map(|p| p[c]).collect(),
+        })
+        .collect()
+}
+
+// IEEE half, rounding to nearest even. Too large goes to infinity, too small to (signed) zero
+pub fn f32_to_half(f: f32) -> u16 {
+    let x = f.to_bits();
+    let sign = ((x >> 16) & 0x8000) as u16;
+    let exp = ((x >> 23) & 0xff) as i32;
+    let mant = x & 0x7f_ffff;
+    if exp == 0xff {
+        return sign | 0x7c00 | if mant != 0 { 0x200 } else { 0 };
+    }
+    let e = exp - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7c00;
+    }
+    let (mut h, rem, halfway) = if e <= 0 {
+        if e < -10 {
+            return sign;
+        }
+        // subnormal, shift the implicit leading one down into the mantissa
+        let m = mant | 0x80_0000;
+        let shift = (14 - e) as u32;
+        (m >> shift, m & ((1 << shift) - 1), 1 << (shift - 1))
+    } else {
+        (((e as u32) << 10) | (mant >> 13), mant & 0x1fff, 0x1000)
+    };
+    if rem > halfway || (rem == halfway && h & 1 == 1) {
+        h += 1; // can carry into the exponent, which is still the right answer
+    }
+    sign | h as u16
+}
+
+fn write_exr_attribute<W: Write>(w: &mut W, name: &str, kind: &str, value: &[u8]) -> io::Result<()> {
+    w.write_all(name.as_bytes())?;
+    w.write_all(&[0])?;
+    w.write_all(kind.as_bytes())?;
+    w.write_all(&[0])?;
+    w.write_all(&(value.len() as i32).to_le_bytes())?;
+    w.write_all(value)
+}
+
+// Single part, uncompressed scanline OpenEXR. Channels go out sorted by name, as readers expect
+pub fn write_exr(
+    path: &Path,
+    width: usize,
+    height: usize,
+    channels: &[ImageChannel],
+    pixel_type: ExrPixelType,
+) -> io::Result<()> {
+    let mut sorted: Vec<&ImageChannel> = channels.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+    if sorted.iter().any(|c| c.data.len() < width * height) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "channel smaller than the image",
+        ));
+    }
+    let (type_id, sample_size) = match pixel_type {
+        ExrPixelType::Half => (1i32, 2),
+        ExrPixelType::Float => (2i32, 4),
+    };
+
+    let mut header: Vec<u8> = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    let mut chlist = Vec::new();
+    for c in sorted.iter() {
+        chlist.extend_from_slice(c.name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&type_id.to_le_bytes());
+        chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+    let mut window = Vec::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1].iter() {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    write_exr_attribute(&mut header, "channels", "chlist", &chlist)?;
+    write_exr_attribute(&mut header, "compression", "compression", &[0])?;
+    write_exr_attribute(&mut header, "dataWindow", "box2i", &window)?;
+    write_exr_attribute(&mut header, "displayWindow", "box2i", &window)?;
+    write_exr_attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+    write_exr_attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes())?;
+    write_exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+    write_exr_attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes())?;
+    header.push(0);
+
+    // one scanline per chunk: y, byte count, then each channel's run of samples
+    let line_size = width * sorted.len() * sample_size;
+    let chunk_size = 8 + line_size;
+    let table_end = header.len() + height * 8;
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&header)?;
+    for y in 0..height {
+        file.write_all(&((table_end + y * chunk_size) as u64).to_le_bytes())?;
+    }
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..height {
+        line.clear();
+        for c in sorted.iter() {
+            for v in c.data[y * width..(y + 1) * width].iter() {
+                match pixel_type {
+                    ExrPixelType::Half => line.extend_from_slice(&f32_to_half(*v).to_le_bytes()),
+                    ExrPixelType::Float => line.extend_from_slice(&v.to_le_bytes()),
+                }
+            }
+        }
+        file.write_all(&(y as i32).to_le_bytes())?;
+        file.write_all(&(line_size as i32).to_le_bytes())?;
+        file.write_all(&line)?;
+    }
+    file.flush()
+}
+
+// Little endian (negative scale) RGB, rows bottom to top
+pub fn write_pfm(path: &Path, width: usize, height: usize, pixels: &[[f32; 3]]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(format!("PF\n{} {}\n-1.0\n", width, height).as_bytes())?;
+    for row in pixels.chunks(width).take(height).rev() {
+        for p in row {
+            for v in p.iter() {
+                file.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
+
+// Float RGB image, row 0 at the top. Used for environment maps
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+fn invalid_data(path: &Path, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), msg),
+    )
+}
+
+pub fn read_hdr_image(path: &Path) -> io::Result<HdrImage> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    match ext.as_deref() {
+        Some("hdr") | Some("pic") => parse_radiance(&bytes).map_err(|e| invalid_data(path, e)),
+        Some("pfm") => parse_pfm(&bytes).map_err(|e| invalid_data(path, e)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "don't know how to read '{}', use a .hdr or .pfm file",
+                path.display()
+            ),
+        )),
+    }
+}
+
+// Reads one '\n' terminated header line starting at *pos
+fn next_line<'a>(bytes: &'a [u8], pos: &mut usize) -> Option<&'a str> {
+    let start = *pos;
+    let len = bytes[start..].iter().position(|b| *b == b'\n')?;
+    *pos = start + len + 1;
+    std::str::from_utf8(&bytes[start..start + len]).ok()
+}
+
+fn rgbe_to_float(rgbe: [u8; 4]) -> [f32; 3] {
+    if rgbe[3] == 0 {
+        return [0.0; 3];
+    }
+    let f = 2f32.powi(rgbe[3] as i32 - (128 + 8));
+    [rgbe[0] as f32 * f, rgbe[1] as f32 * f, rgbe[2] as f32 * f]
+}
+
+// Anything bigger than this is a corrupt header rather than an environment map
+const MAX_HDR_PIXELS: usize = 1 << 28;
+
+// Radiance RGBE, flat or with the per-channel run length encoded scanlines everyone writes
+pub fn parse_radiance(bytes: &[u8]) -> Result<HdrImage, &'static str> {
+    let mut pos = 0;
+    match next_line(bytes, &mut pos) {
+        Some(magic) if magic.starts_with("#?") => {}
+        _ => return Err("not a Radiance file"),
+    }
+    loop {
+        match next_line(bytes, &mut pos) {
+            Some("") => break,
+            Some(l) if l.starts_with("FORMAT=") && l != "FORMAT=32-bit_rle_rgbe" => {
+                return Err("only 32-bit_rle_rgbe is supported")
+            }
+            Some(_) => {}
+            None => return Err("truncated header"),
+        }
+    }
+    let res: Vec<&str> = next_line(bytes, &mut pos)
+        .ok_or("missing resolution")?
+        .split_whitespace()
+        .collect();
+    if res.len() != 4 || res[2] != "+X" || (res[0] != "-Y" && res[0] != "+Y") {
+        return Err("unsupported resolution line, expected -Y <height> +X <width>");
+    }
+    let height: usize = res[1].parse().map_err(|_| "bad height")?;
+    let width: usize = res[3].parse().map_err(|_| "bad width")?;
+    let count = width
+        .checked_mul(height)
+        .filter(|&n| n > 0 && n <= MAX_HDR_PIXELS)
+        .ok_or("unreasonable image size")?;
+
+    let mut pixels = Vec::with_capacity(count);
+    let mut scanline = vec![[0u8; 4]; width];
+    let byte = |pos: &mut usize| -> Result<u8, &'static str> {
+        let b = *bytes.get(*pos).ok_or("truncated pixel data")?;
+        *pos += 1;
+        Ok(b)
+    };
+    for _ in 0..height {
+        let header = match bytes.get(pos..pos + 4) {
+            Some(&[2, 2, hi, lo]) if width >= 8 && width < 0x8000 && hi & 0x80 == 0 => {
+                Some(((hi as usize) << 8) | lo as usize)
+            }
+            _ => None,
+        };
+        if let Some(w) = header {
+            if w != width {
+                return Err("scanline width mismatch");
+            }
+            pos += 4;
+            for c in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = byte(&mut pos)? as usize;
+                    if count > 128 {
+                        let v = byte(&mut pos)?;
+                        let n = count - 128;
+                        if x + n > width {
+                            return Err("bad run length");
+                        }
+                        for p in scanline[x..x + n].iter_mut() {
+                            p[c] = v;
+                        }
+                        x += n;
+                    } else {
+                        if count == 0 || x + count > width {
+                            return Err("bad run length");
+                        }
+                        for p in scanline[x..x + count].iter_mut() {
+                            p[c] = byte(&mut pos)?;
+                        }
+                        x += count;
+                    }
+                }
+            }
+        } else {
+            // flat pixels, with the old style (1, 1, 1, n) repeat markers
+            let mut x = 0;
+            let mut shift = 0;
+            while x < width {
+                let p = [byte(&mut pos)?, byte(&mut pos)?, byte(&mut pos)?, byte(&mut pos)?];
+                if p[0] == 1 && p[1] == 1 && p[2] == 1 && x > 0 {
+                    let n = (p[3] as usize) << shift;
+                    if x + n > width {
+                        return Err("bad run length");
+                    }
+                    let prev = scanline[x - 1];
+                    for q in scanline[x..x + n].iter_mut() {
+                        *q = prev;
+                    }
+                    x += n;
+                    shift += 8;
+                } else {
+                    scanline[x] = p;
+                    x += 1;
+                    shift = 0;
+                }
+            }
+        }
+        pixels.extend(scanline.iter().map(|p| rgbe_to_float(*p)));
+    }
+    if res[0] == "+Y" {
+        pixels = pixels
+            .chunks(width)
+            .rev()
+            .flat_map(|row| row.iter().cloned())
+            .collect();
+    }
+    Ok(HdrImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+// Portable float map: "PF" (rgb) or "Pf" (grey), rows stored bottom to top
+pub fn parse_pfm(bytes: &[u8]) -> Result<HdrImage, &'static str> {
+    let mut pos = 0;
+    let channels = match next_line(bytes, &mut pos).map(|l| l.trim()) {
+        Some("PF") => 3,
+        Some("Pf") => 1,
+        _ => return Err("not a PFM file"),
+    };
+    let dims: Vec<usize> = next_line(bytes, &mut pos)
+        .ok_or("missing size")?
+        .split_whitespace()
+        .map(|t| t.parse().map_err(|_| "bad size"))
+        .collect::<Result<_, _>>()?;
+    if dims.len() != 2 {
+        return Err("bad size");
+    }
+    let (width, height) = (dims[0], dims[1]);
+    let size = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(channels * 4))
+        .ok_or("bad size")?;
+    let scale: f32 = next_line(bytes, &mut pos)
+        .ok_or("missing scale")?
+        .trim()
+        .parse()
+        .map_err(|_| "bad scale")?;
+    let little_endian = scale < 0.0;
+    let data = &bytes[pos..];
+    if data.len() < size {
+        return Err("truncated pixel data");
+    }
+    let value = |i: usize| -> f32 {
+        let b = [data[i * 4], data[i * 4 + 1], data[i * 4 + 2], data[i * 4 + 3]];
+        if little_endian {
+            f32::from_le_bytes(b)
+        } else {
+            f32::from_be_bytes(b)
+        }
+    };
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let i = (y * width + x) * channels;
+            pixels.push(match channels {
+                3 => [value(i), value(i + 1), value(i + 2)],
+                _ => [value(i); 3],
+            });
+        }
+    }
+    Ok(HdrImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tga_and_jpeg_refuse_sides_over_65535() {
+        let image = LdrImage {
+            width: 70000,
+            height: 1,
+            bit_depth: 8,
+            pixels: vec![[0; 3]; 70000],
+        };
+        let dir = std::env::temp_dir();
+        for name in ["too_wide.tga", "too_wide.jpg"].iter() {
+            let path = dir.join(format!("{}_{}", std::process::id(), name));
+            let e = save_ldr(&path, &image, &[], 90).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+            assert!(!path.exists());
+        }
+    }
+
+    #[test]
+    fn reads_rle_radiance() {
+        let mut bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+        // row 0: run length encoded, every channel a run of 8
+        bytes.extend_from_slice(&[2, 2, 0, 8]);
+        bytes.extend_from_slice(&[136, 128, 136, 64, 136, 0, 136, 129]);
+        // row 1: literal bytes
+        bytes.extend_from_slice(&[2, 2, 0, 8]);
+        for c in 0..4u8 {
+            bytes.push(8);
+            bytes.extend((0..8u8).map(|x| if c == 3 { 128 } else { x * 16 }));
+        }
+        let img = parse_radiance(&bytes).unwrap();
+        assert_eq!((img.width, img.height), (8, 2));
+        assert_eq!(img.pixels[0], [1.0, 0.5, 0.0]);
+        assert_eq!(img.pixels[8 + 4], [0.25, 0.25, 0.25]);
+    }
+
+    #[test]
+    fn reads_pfm_bottom_up() {
+        let mut bytes = b"PF\n1 2\n-1.0\n".to_vec();
+        for v in &[1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        let img = parse_pfm(&bytes).unwrap();
+        assert_eq!(img.pixels, vec![[4.0, 5.0, 6.0], [1.0, 2.0, 3.0]]);
+    }
+
+    #[test]
+    fn rejects_truncated_and_oversized_images() {
+        let mut rle = b"#?RADIANCE\n\n-Y 1 +X 8\n".to_vec();
+        rle.extend_from_slice(&[2, 2, 0, 8, 136, 128]);
+        // every prefix that cuts into the header or the pixels must fail cleanly
+        for end in 0..rle.len() {
+            assert!(parse_radiance(&rle[..end]).is_err(), "accepted {} bytes", end);
+        }
+        // a scanline header cut short falls back to flat pixels, which are truncated too
+        assert!(parse_radiance(b"#?RADIANCE\n\n-Y 1 +X 8\n\x02\x02\x00").is_err());
+        assert_eq!(
+            parse_radiance(b"#?RADIANCE\n\n-Y 99999999999 +X 99999999999\n").err(),
+            Some("unreasonable image size")
+        );
+        assert!(parse_radiance(b"#?RADIANCE\n\n+Y 0 +X 0\n").is_err());
+
+        let mut pfm = b"PF\n2 1\n-1.0\n".to_vec();
+        pfm.extend((0..24).map(|i| i as u8));
+        for end in 0..pfm.len() {
+            assert!(parse_pfm(&pfm[..end]).is_err(), "accepted {} bytes", end);
+        }
+        assert!(parse_pfm(&pfm).is_ok());
+        let huge = format!("PF\n{} {}\n-1.0\n", usize::MAX / 2, 3);
+        assert_eq!(parse_pfm(huge.as_bytes()).err(), Some("bad size"));
+    }
+
+    #[test]
+    fn converts_to_half() {
+        assert_eq!(f32_to_half(0.0), 0x0000);
+        assert_eq!(f32_to_half(-0.0), 0x8000);
+        assert_eq!(f32_to_half(1.0), 0x3c00);
+        assert_eq!(f32_to_half(-2.5), 0xc100);
+        assert_eq!(f32_to_half(65504.0), 0x7bff);
+        assert_eq!(f32_to_half(1e6), 0x7c00);
+        assert_eq!(f32_to_half(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_half(1.0 + 2f32.powi(-11)), 0x3c00); // tie goes to even
+        assert_eq!(f32_to_half(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+    }
+
+    #[test]
+    fn pfm_round_trips() {
+        let path = std::env::temp_dir().join("imageio_round_trip.pfm");
+        let pixels = vec![[0.25, 1.5, 1000.0], [0.0, -1.0, 2.0], [3.0, 4.0, 5.0], [6.0, 7.0, 8.0]];
+        write_pfm(&path, 2, 2, &pixels).unwrap();
+        let img = read_hdr_image(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!((img.width, img.height), (2, 2));
+        assert_eq!(img.pixels, pixels);
+    }
+}
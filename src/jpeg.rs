@@ -0,0 +1,361 @@
+// Baseline JPEG encoder: JFIF, YCbCr without chroma subsampling, the Annex K quantization and
+// Huffman tables scaled the way libjpeg scales them for a 1-100 quality
+
+use std::io;
+
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+#[rustfmt::skip]
+const LUMA_QUANT: [u8; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61,
+    12, 12, 14, 19, 26, 58, 60, 55,
+    14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62,
+    18, 22, 37, 56, 68, 109, 103, 77,
+    24, 35, 55, 64, 81, 104, 113, 92,
+    49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103, 99,
+];
+#[rustfmt::skip]
+const CHROMA_QUANT: [u8; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99,
+    18, 21, 26, 66, 99, 99, 99, 99,
+    24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+// (code lengths 1-16 counts, symbols) as they go in the DHT segment
+const DC_LUMA_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const DC_CHROMA_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const AC_LUMA_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+#[rustfmt::skip]
+const AC_LUMA_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+const AC_CHROMA_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+#[rustfmt::skip]
+const AC_CHROMA_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+// libjpeg's quality curve: 50 is the table as printed, 100 is all ones
+fn scale_quant(base: &[u8; 64], quality: u8) -> [u8; 64] {
+    let q = quality.max(1).min(100) as u32;
+    let scale = if q < 50 { 5000 / q } else { 200 - 2 * q };
+    let mut out = [0u8; 64];
+    for (o, b) in out.iter_mut().zip(base.iter()) {
+        *o = ((*b as u32 * scale + 50) / 100).max(1).min(255) as u8;
+    }
+    out
+}
+
+// (code, length) for every symbol, from the canonical code in Annex C
+fn huffman_codes(bits: &[u8; 16], values: &[u8]) -> [(u16, u8); 256] {
+    let mut codes = [(0u16, 0u8); 256];
+    let mut code = 0u16;
+    let mut k = 0;
+    for len in 1..=16u8 {
+        for _ in 0..bits[len as usize - 1] {
+            codes[values[k] as usize] = (code, len);
+            code += 1;
+            k += 1;
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    count: u32,
+}
+impl BitWriter {
+    fn write(&mut self, bits: u16, len: u8) {
+        self.acc = (self.acc << len) | (bits as u32 & ((1 << len) - 1));
+        self.count += len as u32;
+        while self.count >= 8 {
+            let byte = (self.acc >> (self.count - 8)) as u8;
+            self.out.push(byte);
+            if byte == 0xff {
+                self.out.push(0); // byte stuffing
+            }
+            self.count -= 8;
+        }
+        self.acc &= (1 << self.count) - 1;
+    }
+    fn flush(&mut self) {
+        if self.count > 0 {
+            let pad = 8 - self.count as u8;
+            self.write((1 << pad) - 1, pad);
+        }
+    }
+}
+
+fn category(v: i32) -> u8 {
+    (32 - v.abs().leading_zeros()) as u8
+}
+
+// Negative values are sent as their ones' complement in `category` bits
+fn magnitude_bits(v: i32, cat: u8) -> u16 {
+    if v < 0 {
+        (v + (1 << cat) - 1) as u16
+    } else {
+        v as u16
+    }
+}
+
+fn fdct(block: &[f32; 64], cos: &[[f32; 8]; 8]) -> [f32; 64] {
+    let mut tmp = [0f32; 64];
+    for y in 0..8 {
+        for u in 0..8 {
+            let mut s = 0.0;
+            for x in 0..8 {
+                s += block[y * 8 + x] * cos[u][x];
+            }
+            tmp[y * 8 + u] = s;
+        }
+    }
+    let mut out = [0f32; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            let mut s = 0.0;
+            for y in 0..8 {
+                s += tmp[y * 8 + u] * cos[v][y];
+            }
+            out[v * 8 + u] = s;
+        }
+    }
+    out
+}
+
+struct Component<'a> {
+    quant: [u8; 64],
+    dc: [(u16, u8); 256],
+    ac: [(u16, u8); 256],
+    plane: &'a [f32],
+    prev_dc: i32,
+}
+
+fn encode_block(
+    bits: &mut BitWriter,
+    c: &mut Component,
+    width: usize,
+    height: usize,
+    bx: usize,
+    by: usize,
+    cos: &[[f32; 8]; 8],
+) {
+    // edge blocks repeat the last row / column
+    let mut block = [0f32; 64];
+    for y in 0..8 {
+        for x in 0..8 {
+            let px = (bx + x).min(width - 1);
+            let py = (by + y).min(height - 1);
+            block[y * 8 + x] = c.plane[py * width + px] - 128.0;
+        }
+    }
+    let coeffs = fdct(&block, cos);
+    let mut q = [0i32; 64];
+    for (k, &n) in ZIGZAG.iter().enumerate() {
+        q[k] = (coeffs[n] / c.quant[n] as f32).round() as i32;
+    }
+
+    let diff = q[0] - c.prev_dc;
+    c.prev_dc = q[0];
+    let cat = category(diff);
+    let (code, len) = c.dc[cat as usize];
+    bits.write(code, len);
+    if cat > 0 {
+        bits.write(magnitude_bits(diff, cat), cat);
+    }
+
+    let mut run = 0;
+    for k in 1..64 {
+        if q[k] == 0 {
+            run += 1;
+            continue;
+        }
+        while run > 15 {
+            let (code, len) = c.ac[0xf0];
+            bits.write(code, len);
+            run -= 16;
+        }
+        let cat = category(q[k]);
+        let (code, len) = c.ac[(run << 4) | cat as usize];
+        bits.write(code, len);
+        bits.write(magnitude_bits(q[k], cat), cat);
+        run = 0;
+    }
+    if run > 0 {
+        let (code, len) = c.ac[0x00];
+        bits.write(code, len);
+    }
+}
+
+fn segment(out: &mut Vec<u8>, marker: u8, data: &[u8]) {
+    out.extend_from_slice(&[0xff, marker]);
+    out.extend_from_slice(&((data.len() + 2) as u16).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+// rgb is row 0 at the top. Each comment becomes its own COM segment
+pub fn encode(width: usize, height: usize, rgb: &[[u8; 3]], quality: u8, comments: &[String]) -> io::Result<Vec<u8>> {
+    // the frame header has 16 bits for each side
+    if width > u16::MAX as usize || height > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}x{} is too big for a JPEG", width, height),
+        ));
+    }
+    let luma_quant = scale_quant(&LUMA_QUANT, quality);
+    let chroma_quant = scale_quant(&CHROMA_QUANT, quality);
+
+    let mut out = vec![0xff, 0xd8];
+    segment(&mut out, 0xe0, b"JFIF\0\x01\x01\x00\x00\x01\x00\x01\x00\x00");
+    for c in comments {
+        let bytes = c.as_bytes();
+        segment(&mut out, 0xfe, &bytes[..bytes.len().min(65000)]);
+    }
+    let mut dqt = vec![0u8];
+    dqt.extend(ZIGZAG.iter().map(|&n| luma_quant[n]));
+    dqt.push(1);
+    dqt.extend(ZIGZAG.iter().map(|&n| chroma_quant[n]));
+    segment(&mut out, 0xdb, &dqt);
+
+    let mut sof = vec![8];
+    sof.extend_from_slice(&(height as u16).to_be_bytes());
+    sof.extend_from_slice(&(width as u16).to_be_bytes());
+    sof.extend_from_slice(&[3, 1, 0x11, 0, 2, 0x11, 1, 3, 0x11, 1]);
+    segment(&mut out, 0xc0, &sof);
+
+    let mut dht = Vec::new();
+    for (class_id, bits, values) in [
+        (0x00u8, &DC_LUMA_BITS, &DC_VALUES[..]),
+        (0x10, &AC_LUMA_BITS, &AC_LUMA_VALUES[..]),
+        (0x01, &DC_CHROMA_BITS, &DC_VALUES[..]),
+        (0x11, &AC_CHROMA_BITS, &AC_CHROMA_VALUES[..]),
+    ]
+    .iter()
+    {
+        dht.push(*class_id);
+        dht.extend_from_slice(*bits);
+        dht.extend_from_slice(values);
+    }
+    segment(&mut out, 0xc4, &dht);
+    segment(&mut out, 0xda, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+
+    let mut planes = vec![Vec::with_capacity(width * height); 3];
+    for p in rgb.iter().take(width * height) {
+        let (r, g, b) = (p[0] as f32, p[1] as f32, p[2] as f32);
+        planes[0].push(0.299 * r + 0.587 * g + 0.114 * b);
+        planes[1].push(-0.168_736 * r - 0.331_264 * g + 0.5 * b + 128.0);
+        planes[2].push(0.5 * r - 0.418_688 * g - 0.081_312 * b + 128.0);
+    }
+    let mut cos = [[0f32; 8]; 8];
+    for u in 0..8 {
+        let cu = if u == 0 { 0.5f32.sqrt() } else { 1.0 };
+        for x in 0..8 {
+            cos[u][x] = 0.5
+                * cu
+                * (((2 * x + 1) * u) as f32 * std::f32::consts::PI / 16.0).cos();
+        }
+    }
+    let mut components = [
+        Component {
+            quant: luma_quant,
+            dc: huffman_codes(&DC_LUMA_BITS, &DC_VALUES),
+            ac: huffman_codes(&AC_LUMA_BITS, &AC_LUMA_VALUES),
+            plane: &planes[0],
+            prev_dc: 0,
+        },
+        Component {
+            quant: chroma_quant,
+            dc: huffman_codes(&DC_CHROMA_BITS, &DC_VALUES),
+            ac: huffman_codes(&AC_CHROMA_BITS, &AC_CHROMA_VALUES),
+            plane: &planes[1],
+            prev_dc: 0,
+        },
+        Component {
+            quant: chroma_quant,
+            dc: huffman_codes(&DC_CHROMA_BITS, &DC_VALUES),
+            ac: huffman_codes(&AC_CHROMA_BITS, &AC_CHROMA_VALUES),
+            plane: &planes[2],
+            prev_dc: 0,
+        },
+    ];
+    let mut bits = BitWriter {
+        out: out,
+        acc: 0,
+        count: 0,
+    };
+    for by in (0..height).step_by(8) {
+        for bx in (0..width).step_by(8) {
+            for c in components.iter_mut() {
+                encode_block(&mut bits, c, width, height, bx, by, &cos);
+            }
+        }
+    }
+    bits.flush();
+    let mut out = bits.out;
+    out.extend_from_slice(&[0xff, 0xd9]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn huffman_tables_cover_every_symbol() {
+        for (bits, values) in [(&AC_LUMA_BITS, &AC_LUMA_VALUES), (&AC_CHROMA_BITS, &AC_CHROMA_VALUES)].iter() {
+            assert_eq!(bits.iter().map(|b| *b as usize).sum::<usize>(), values.len());
+            let mut sorted = values.to_vec();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(sorted.len(), 162);
+            // EOB, ZRL and every run/size pair with size 1-10
+            assert!(sorted.iter().all(|v| *v == 0 || *v == 0xf0 || (1..=10).contains(&(v & 0xf))));
+        }
+    }
+
+    #[test]
+    fn encodes_markers_and_comments() {
+        let rgb = vec![[200u8, 30, 30]; 10 * 9];
+        let jpg = encode(10, 9, &rgb, 90, &["Seed: 7".to_string()]).unwrap();
+        assert_eq!(&jpg[..2], &[0xff, 0xd8]);
+        assert_eq!(&jpg[jpg.len() - 2..], &[0xff, 0xd9]);
+        assert!(jpg.windows(7).any(|w| w == b"Seed: 7"));
+        // sides have to fit the 16 bit frame header
+        assert_eq!(encode(1, 70000, &[], 90, &[]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,8 +1,24 @@
 #![feature(partition_point)]
 pub mod BVH;
+pub mod aov;
+pub mod background;
 pub mod camera;
+pub mod denoise;
+pub mod filter;
 pub mod hit;
+pub mod imageio;
+pub mod jpeg;
+pub mod light;
 pub mod material;
+pub mod mesh;
 pub mod objLoader;
 pub mod ray;
+pub mod render;
+pub mod rng;
+pub mod sampler;
+pub mod sceneLoader;
+pub mod tiles;
+pub mod tonemap;
+pub mod transform;
 pub mod vectors;
+pub mod world;
@@ -0,0 +1,299 @@
+use crate::hit::{sphere_uv, HitInfo, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vectors::Vector3;
+use crate::vectors::Vector3 as Color;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+pub enum LightShape {
+    Sphere {
+        center: Vector3<f64>,
+        radius: f64,
+    },
+    Tri {
+        p0: Vector3<f64>,
+        p1: Vector3<f64>,
+        p2: Vector3<f64>,
+    },
+}
+
+pub struct Light {
+    pub shape: LightShape,
+    pub mat: Arc<dyn Material + Send + Sync>,
+    pub area: f64,
+    pub object_id: u32,
+}
+// 1 - cos of the half angle of the cone a sphere subtends from `from`, or None from inside it.
+// Written as sin^2 / (1 + cos) so small far away spheres don't lose it all to rounding
+fn cone_extent(from: &Vector3<f64>, center: &Vector3<f64>, radius: f64) -> Option<f64> {
+    let sin2 = radius * radius / (*center - *from).sqrmagnitude();
+    if sin2 >= 1.0 {
+        return None;
+    }
+    Some(sin2 / (1.0 + (1.0 - sin2).sqrt()))
+}
+
+impl Light {
+    // Point on the light seen from `from` and the solid angle pdf of picking it. Spheres seen from outside are sampled over the cone they subtend, so no sample is wasted on
+    // the far side, everything else uniformly over its area
+    fn sample_from(&self, from: &Vector3<f64>, u: (f64, f64)) -> Option<(Vector3<f64>, f64)> {
+        if let LightShape::Sphere { center, radius } = &self.shape {
+            if let Some(extent) = cone_extent(from, center, *radius) {
+                let axis = *center - *from;
+                let dist = axis.magnitude();
+                let w = axis / dist;
+                let a = if w.x.abs() > 0.9 { Vector3::up() } else { Vector3::right() };
+                let s = w.cross(&a).normalized();
+                let t = w.cross(&s);
+                let cos = 1.0 - u.0 * extent;
+                let sin = (1.0 - cos * cos).max(0.0).sqrt();
+                let phi = 2.0 * PI * u.1;
+                let dir = cos * w + (sin * phi.cos()) * s + (sin * phi.sin()) * t;
+                // the nearer of the two places that direction crosses the sphere
+                let along = dist * cos - (radius * radius - dist * dist * sin * sin).max(0.0).sqrt();
+                let p = *from + along * dir;
+                return Some((p, 1.0 / (2.0 * PI * extent)));
+            }
+        }
+        let (p, normal) = self.sample_point(u);
+        let pdf = self.pdf(from, &p, &normal);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((p, pdf))
+    }
+    // Solid angle pdf of sample_from picking p, with `normal` the light's normal there
+    fn pdf(&self, from: &Vector3<f64>, p: &Vector3<f64>, normal: &Vector3<f64>) -> f64 {
+        if let LightShape::Sphere { center, radius } = &self.shape {
+            if let Some(extent) = cone_extent(from, center, *radius) {
+                return 1.0 / (2.0 * PI * extent);
+            }
+        }
+        let d = *p - *from;
+        let dist2 = d.sqrmagnitude();
+        let cos = (normal.dot(&d) / dist2.sqrt()).abs();
+        if cos < 1e-8 {
+            return 0.0;
+        }
+        dist2 / (cos * self.area)
+    }
+    // Uniform point on the surface and the outward normal there
+    fn sample_point(&self, u: (f64, f64)) -> (Vector3<f64>, Vector3<f64>) {
+        match &self.shape {
+            LightShape::Sphere { center, radius } => {
+                let n = Vector3::<f64>::unit_vector_from(u);
+                (*center + *radius * n, n)
+            }
+            LightShape::Tri { p0, p1, p2 } => {
+                let su = u.0.sqrt();
+                let b1 = u.1 * su;
+                let b0 = 1.0 - su;
+                let p = b0 * *p0 + b1 * *p1 + (1.0 - b0 - b1) * *p2;
+                (p, (*p1 - *p0).cross(&(*p2 - *p0)).normalized())
+            }
+        }
+    }
+}
+
+pub struct LightSample<'a> {
+    pub p: Vector3<f64>,
+    pub dir: Vector3<f64>, // unit vector from the shading point to p
+    pub dist: f64,
+    pub pdf: f64, // solid angle
+    pub light: &'a Light,
+}
+
+// Every emissive sphere and triangle in the world. Lights are picked in proportion to their area,
+// then sampled on their own (see Light::sample_from)
+pub struct LightList {
+    pub lights: Vec<Light>,
+    cdf: Vec<f64>,
+    total_area: f64,
+    by_object: HashMap<u32, usize>, // first light of each object
+}
+impl LightList {
+    pub fn new() -> LightList {
+        LightList {
+            lights: vec![],
+            cdf: vec![],
+            total_area: 0.0,
+            by_object: HashMap::new(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+    fn add(&mut self, light: Light) {
+        if light.area <= 0.0 {
+            return;
+        }
+        self.total_area += light.area;
+        self.cdf.push(self.total_area);
+        self.by_object.entry(light.object_id).or_insert(self.lights.len());
+        self.lights.push(light);
+    }
+    pub fn add_sphere(
+        &mut self,
+        center: Vector3<f64>,
+        radius: f64,
+        mat: Arc<dyn Material + Send + Sync>,
+        object_id: u32,
+    ) {
+        self.add(Light {
+            shape: LightShape::Sphere { center, radius },
+            mat,
+            area: 4.0 * PI * radius * radius,
+            object_id,
+        });
+    }
+    pub fn add_tri(
+        &mut self,
+        p0: Vector3<f64>,
+        p1: Vector3<f64>,
+        p2: Vector3<f64>,
+        mat: Arc<dyn Material + Send + Sync>,
+        object_id: u32,
+    ) {
+        self.add(Light {
+            shape: LightShape::Tri { p0, p1, p2 },
+            mat,
+            area: 0.5 * (p1 - p0).cross(&(p2 - p0)).magnitude(),
+            object_id,
+        });
+    }
+    // Solid angle pdf of sample() picking the point p of object object_id seen from `from`, with
+    // `normal` the light's normal there. The triangles of a mesh light all give the same answer, so
+    // any of them will do
+    pub fn pdf(&self, object_id: u32, from: &Vector3<f64>, p: &Vector3<f64>, normal: &Vector3<f64>) -> f64 {
+        match self.by_object.get(&object_id) {
+            Some(&i) => {
+                let light = &self.lights[i];
+                light.area / self.total_area * light.pdf(from, p, normal)
+            }
+            None => 0.0,
+        }
+    }
+    pub fn sample(&self, from: &Vector3<f64>, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let x = sampler.get_1d() * self.total_area;
+        let i = self.cdf.partition_point(|c| *c <= x).min(self.lights.len() - 1);
+        let light = &self.lights[i];
+        let (p, pdf) = light.sample_from(from, sampler.get_2d())?;
+        let d = p - *from;
+        let dist = d.magnitude();
+        if dist < 1e-8 {
+            return None;
+        }
+        Some(LightSample {
+            p,
+            dir: d / dist,
+            dist,
+            pdf: light.area / self.total_area * pdf,
+            light,
+        })
+    }
+}
+
+// Radiance the light sends back along the sample direction, or zero if something is in the way
+pub fn light_radiance(sample: &LightSample, from: &Vector3<f64>, world: &dyn Hittable) -> Color<f64> {
+    let shadow = Ray::new(*from, sample.dir);
+    if world.hit(&shadow, 0.001, sample.dist - 0.001).is_some() {
+        return Color::zero();
+    }
+    let outward = match &sample.light.shape {
+        LightShape::Sphere { center, .. } => (sample.p - *center).normalized(),
+        LightShape::Tri { p0, p1, p2 } => (*p1 - *p0).cross(&(*p2 - *p0)).normalized(),
+    };
+    let front_face = shadow.dir.dot(&outward) < 0.0;
+    let hit = HitInfo {
+        t: sample.dist,
+        p: sample.p,
+        normal: if front_face { outward } else { &outward * -1.0 },
+        front_face,
+        uv: match &sample.light.shape {
+            LightShape::Sphere { .. } => sphere_uv(&outward),
+            LightShape::Tri { .. } => Vector3::zero(),
+        },
+        mat: &sample.light.mat,
+        object_id: sample.light.object_id,
+    };
+    sample.light.mat.emitted(&shadow, &hit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Emissive;
+    use crate::sampler::SamplerKind;
+
+    #[test]
+    fn samples_tri_light_with_solid_angle_pdf() {
+        let mut lights = LightList::new();
+        let mat = Arc::new(Emissive {
+            albedo: Color::one(),
+            emission: 1.0,
+        });
+        // unit right triangle in the y=2 plane, area 0.5
+        lights.add_tri(
+            Vector3::from_tuple((0.0, 2.0, 0.0)),
+            Vector3::from_tuple((1.0, 2.0, 0.0)),
+            Vector3::from_tuple((0.0, 2.0, 1.0)),
+            mat,
+            0,
+        );
+        let from = Vector3::zero();
+        let mut sampler = SamplerKind::Sobol.create(1, 100);
+        for i in 0..100 {
+            sampler.start_sample(0, i);
+            let s = lights.sample(&from, &mut *sampler).unwrap();
+            assert!((s.p.y - 2.0).abs() < 1e-12);
+            assert!(s.p.x >= 0.0 && s.p.z >= 0.0 && s.p.x + s.p.z <= 1.0 + 1e-12);
+            let cos = 2.0 / s.dist;
+            let expected = s.dist * s.dist / (cos * 0.5);
+            assert!((s.pdf - expected).abs() < 1e-9 * expected);
+            // what MIS asks for when a bounce lands on the same point
+            let pdf = lights.pdf(0, &from, &s.p, &Vector3::up());
+            assert!((pdf - s.pdf).abs() < 1e-9 * expected);
+        }
+    }
+
+    // A sphere of radius 1 at distance 2 subtends a cone of half angle 30 degrees. Every sample lands
+    // on the half of the cap facing the shading point with the cone's constant pdf, and the directions
+    // spread evenly over the cone
+    #[test]
+    fn samples_sphere_light_over_its_cone() {
+        let mut lights = LightList::new();
+        let mat = Arc::new(Emissive {
+            albedo: Color::one(),
+            emission: 1.0,
+        });
+        let center = Vector3::from_tuple((0.0, 0.0, -2.0));
+        lights.add_sphere(center, 1.0, mat, 4);
+        let from = Vector3::zero();
+        let cone = 2.0 * PI * (1.0 - (3.0f64).sqrt() / 2.0);
+        let mut sampler = SamplerKind::Sobol.create(1, 256);
+        let mut mean_cos = 0.0;
+        for i in 0..256 {
+            sampler.start_sample(0, i);
+            let s = lights.sample(&from, &mut *sampler).unwrap();
+            let normal = s.p - center;
+            assert!((normal.magnitude() - 1.0).abs() < 1e-9);
+            assert!(normal.dot(&(from - s.p)) >= -1e-9, "{:?} is on the far side", s.p);
+            assert!((s.pdf - 1.0 / cone).abs() < 1e-9);
+            assert!((lights.pdf(4, &from, &s.p, &normal) - s.pdf).abs() < 1e-9);
+            mean_cos += -s.dir.z / 256.0;
+        }
+        // uniform over the cap of the unit sphere of directions between cos = sqrt(3)/2 and 1
+        assert!((mean_cos - (1.0 + (3.0f64).sqrt() / 2.0) / 2.0).abs() < 1e-3, "{}", mean_cos);
+        assert_eq!(lights.pdf(5, &from, &center, &Vector3::up()), 0.0);
+
+        // from inside there's no cone, so the whole surface is sampled
+        let s = lights.sample(&center, &mut *sampler).unwrap();
+        assert!((s.pdf - 1.0 / (4.0 * PI)).abs() < 1e-9);
+    }
+}
@@ -2,234 +2,38 @@
 use rand::prelude::*;
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
-use raytracing_one_weekend::camera::Camera;
-use raytracing_one_weekend::hit::*;
-use raytracing_one_weekend::material::*;
+use raytracing_one_weekend::aov;
+use raytracing_one_weekend::aov::Aov;
+use raytracing_one_weekend::denoise::Denoiser;
+use raytracing_one_weekend::filter::Filter;
+use raytracing_one_weekend::imageio;
+use raytracing_one_weekend::imageio::ExrPixelType;
 use raytracing_one_weekend::objLoader;
-use raytracing_one_weekend::objLoader::*;
-use raytracing_one_weekend::ray::Ray;
+use raytracing_one_weekend::render::*;
+use raytracing_one_weekend::sceneLoader;
+use raytracing_one_weekend::sceneLoader::*;
+use raytracing_one_weekend::tiles::Tile;
+use raytracing_one_weekend::tonemap::ToneMapping;
+use raytracing_one_weekend::transform::Transform;
 use raytracing_one_weekend::vectors::Vector3;
 use raytracing_one_weekend::vectors::Vector3 as Color;
-use raytracing_one_weekend::BVH::{bvhNode, Bounds};
-use std::collections::HashMap;
+use raytracing_one_weekend::world::*;
+use raytracing_one_weekend::BVH::Bounds;
+mod cli;
 use std::vec::Vec;
-use rayon::prelude::*;
 // use raytracing_one_weekend::vectors::Vector3 as P    oint3;
-use std::fs::File;
-use std::io::prelude::*;
-use std::sync::Arc;
-use std::{thread, time};
+use std::path::Path;
 extern crate minifb;
 
-use minifb::{Key, Window, WindowOptions};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window};
 
-pub static RAY_TRI_TESTS: AtomicUsize = AtomicUsize::new(0);
-pub static RAY_TRI_ISECT: AtomicUsize = AtomicUsize::new(0);
-pub static TRI_COUNT: AtomicUsize = AtomicUsize::new(0);
-pub static RAY_COUNT: AtomicUsize = AtomicUsize::new(0);
-// fn buildppm() -> std::io::Result<()> {
-//     let w = 256;
-//     let h = 256;
-//     let mut file = File::create("test.ppm")?;
-//     file.write(format!("P3 \n{} {} \n255 \n", w, h).as_bytes())?;
-//     for i in (0..(h)).rev() {
-//         if (i % (h / 8)) == 0 {
-//             println!("{}/{} lines complete", 256 - i, h);
-//         };
-//         for j in (0..(w)).rev() {
-//             let r = i as f64 / (h - 1) as f64;
-//             let g = j as f64 / (w - 1) as f64;
-//             let b = 0.5;
-//             file.write(
-//                 format!(
-//                     "{} {} {} ",
-//                     (r * 255.999) as i64,
-//                     (g * 255.999) as i64,
-//                     (b * 255.999) as i64
-//                 )
-//                 .as_bytes(),
-//             )?;
-//         }
-//         file.write(b"\n");
-//     }
-//     Ok(())
-// }
 // #[derive(Debug, Clone, PartialEq)]
 
-fn clamp(x: f64, min: f64, max: f64) -> f64 {
-    if x < min {
-        return min;
-    };
-    if x > max {
-        return max;
-    };
-    return x;
-}
-fn hitsphere(s: &Sphere, r: &Ray) -> f64 {
-    let oc = r.origin - s.center;
-    let a = r.dir.sqrmagnitude();
-    let half_b = oc.dot(&r.dir);
-    let c = oc.sqrmagnitude() - (s.radius * s.radius);
-    let discriminant = half_b * half_b - a * c;
-    if discriminant < 0.0 {
-        return -1.0;
-    } else {
-        return -half_b - discriminant.sqrt() / a;
-    }
-}
-
-fn raycolor (r: &Ray, world: &dyn Hittable, depth: u32) -> Color<f64>
-{
-    if depth <= 0 {
-        return Vector3::zero();
-    }
-    RAY_COUNT.store(
-        RAY_COUNT.load(Ordering::Acquire) + 1 as usize,
-        Ordering::Relaxed,
-    );
-    match world.hit(r, 0.001, f64::INFINITY) {
-        Some(hit) => match hit.mat.scatter(r, &hit) {
-            Some(result) => return result.attenuation * &raycolor(&result.ray, world, depth - 1),
-            None => return Color::zero(),
-        },
-        None => {
-            let t = 0.5 * (r.dir.normalized().y + 1.0);
-            let botcolor = Color {
-                x: 1.0,
-                y: 1.0,
-                z: 1.0,
-            };
-            let topcolor = Color {
-                x: 0.5,
-                y: 0.7,
-                z: 1.0,
-            };
-            return (1.0 - t) * (botcolor) + (t * topcolor);
-        }
-    }
-}
-struct World{
-    objects: HittableList,
-    materials: HashMap<String, Arc<dyn Material + Send + Sync >>,
-}
-enum matTypes {
-    lambert,
-    metal,
-    dialectric,
-    emissive,
-    normal,
-}
-impl World{
-    pub fn addMat(
-        &mut self,
-        name: String,
-        m: matTypes,
-        col: (f64, f64, f64),
-        rough: f64,
-        ior: f64,
-    ) {
-        let c = Color {
-            x: col.0,
-            y: col.1,
-            z: col.2,
-        };
-        let material = match m {
-            matTypes::normal => Arc::new(Normal {}) as Arc<dyn Material + Send + Sync>,
-            matTypes::lambert => Arc::new(Lambert { albedo: c }) as Arc<dyn Material + Send + Sync>,
-            matTypes::metal => Arc::new(Metal {
-                albedo: c,
-                fuzz: rough,
-            }) as Arc<dyn Material + Send + Sync>,
-            matTypes::dialectric => Arc::new(Dialectric {
-                albedo: c,
-                fuzz: rough,
-                ref_idx: ior,
-            }) as Arc<dyn Material + Send + Sync>,
-            matTypes::emissive => Arc::new(Emissive {
-                albedo: c,
-                emission: rough,
-            }),
-        };
-        self.materials.insert(name, material);
-    }
-    pub fn addSphere(&mut self, p: (f64, f64, f64), r: f64, mat: String) {
-        let m = self.materials.get(&mat).unwrap();
-        self.objects.add(Arc::new(Sphere {
-            center: Vector3::from_tuple(p),
-            radius: r,
-            mat: Arc::clone(m),
-            bbox: Bounds::fromSphere(Vector3::from_tuple(p), r),
-        }));
-    }
-    #[rustfmt::skip]
-    pub fn addTri(
-        &mut self,
-        p0: (f64, f64, f64),
-        p1: (f64, f64, f64),
-        p2: (f64, f64, f64),
-        mat: String,
-    ) {
-        let m = self.materials.get(&mat).unwrap();
-        let p0 = Vector3::from_tuple(p0);
-        let p1 = Vector3::from_tuple(p1);
-        let p2 = Vector3::from_tuple(p2);
-        let mut bbox = Bounds::new();
-        bbox.fitPoints(vec![p0, p1, p2]);
-        self.objects.add(Arc::new(Tri {
-            v0: Vert { P: p0, UV: Vector3::zero(), N: Vector3::zero()},
-            v1: Vert { P: p1, UV: Vector3::zero(), N: Vector3::zero()},
-            v2: Vert { P: p2, UV: Vector3::zero(), N: Vector3::zero()},
-            mat: Arc::clone(m),
-            bbox: bbox
-        }))
-    }
-    #[rustfmt::skip]
-    pub fn addTriMesh(
-        &mut self,
-        tris: &Vec<objLoader::TriData>,
-        offset: Vector3<f64>,
-        mat: String,
-    ) {
-        let mut tempMesh = HittableList {
-            objects: vec![],
-            bbox: Bounds::new(),
-        };
-        let m = self.materials.get(&mat).unwrap();
-        for t in tris {
-            TRI_COUNT.store(TRI_COUNT.load(Ordering::Acquire) + 1, Ordering::Relaxed);
-            // println!("{:?}", t);
-            let mut bbox = Bounds::new();
-            bbox.fitPoints(vec![t.v0+ offset, t.v1+ offset, t.v2+ offset]);
-            
-            tempMesh.add(Arc::new(Tri {
-                v0: Vert{ P: t.v0 + offset, UV: t.vt0, N: t.vn0},
-                v1: Vert{ P: t.v1 + offset, UV: t.vt1, N: t.vn1},
-                v2: Vert{ P: t.v2 + offset, UV: t.vt2, N: t.vn2},
-                mat: Arc::clone(m),
-                bbox: bbox,
-            }));
-        }
-        // tempMesh = bvhNodecreate_from_hlist(tempMesh);
-        // self.objects.add(Arc::new(tempMesh));
-        self.objects.add(Arc::new(bvhNode::create_from_hlist(Arc::new(tempMesh)).unwrap()));
-        // self.objects.add(Arc::new(Tri {
-        //     v0: Vector3::from_tuple(v0),
-        //     v1: Vector3::from_tuple(v1),
-        //     v2: Vector3::from_tuple(v2),
-        //     mat: Arc::clone(m),
-        // }))
-    }
-}
 #[rustfmt::skip]
-fn makeWorld<'a>() -> World{
-    let mut world = World {
-        objects: HittableList {
-            objects: vec![],
-            bbox: Bounds::infinity(),
-        },
-        materials: HashMap::new(),
-    };
-    let OBJ = objLoader::objToTrilist().unwrap();
+fn makeWorld<'a>(seed: u64) -> World{
+    let mut world = World::new();
+    // built in, so the demo doesn't depend on where it's run from
+    let OBJ = objLoader::read_obj(include_str!("blendermonkey_attributes.obj").as_bytes()).unwrap();
     world.addMat("grey".to_string(), matTypes::lambert, (0.5, 0.5, 0.5), 0.5, 1.0);
     world.addMat("glass".to_string(), matTypes::dialectric, (1.0, 1.0, 1.0), 0.001, 1.5);
     world.addMat("Monkey".to_string(), matTypes::metal, (0.7, 0.6, 0.5), 0.01, 1.0);
@@ -237,18 +41,19 @@ fn makeWorld<'a>() -> World{
     world.addSphere((0.0,-1000.0,0.0), 1000.0, "grey".to_string());
     world.addTriMesh(
         &OBJ,
-        4.0 * Vector3::right() + (0.2 * Vector3::up()),
+        &Transform::translation(4.0 * Vector3::right() + (0.2 * Vector3::up())),
         "Monkey".to_string(),
+        false,
     );
     world.addSphere((-4.0,1.0,0.0), 1.0, "bigSphere".to_string());
     world.addSphere((0.0,1.0,0.0), 1.0, "glass".to_string());
 
-    let mut rng = thread_rng();
+    let mut rng = StdRng::seed_from_u64(seed);
     for a in -11..11 {
         for b in -11..11
         {
             let a:f64 = a.into();
-            let b:f64 = a.into();
+            let b:f64 = b.into();
             let choose_mat = rng.gen_range(0.0, 15.0);
             let center = Vector3::from_tuple((a + 0.9*rng.gen_range(0.0,15.0), 0.2, b+ 0.9*rng.gen_range(0.0,15.0)));
             if (center - Vector3::from_tuple((4.0, 0.2, 0.0))).magnitude() > 0.9 {
@@ -256,13 +61,13 @@ fn makeWorld<'a>() -> World{
 
                 if choose_mat < 0.8 * 15.0 {
                     // diffuse
-                    let color= Color::<f64>::random() * &Color::<f64>::random();
+                    let color = Color::<f64>::from_tuple((rng.gen(), rng.gen(), rng.gen())) * &Color::<f64>::from_tuple((rng.gen(), rng.gen(), rng.gen()));
                     world.addMat(name.clone(), matTypes::lambert, (color.x, color.y, color.z), 0.5, 1.0);
                     world.addSphere((center.x,center.y,center.z), 0.2, name);
                 } else if (choose_mat < 0.95 * 15.0) {
                     // metal
 
-                    let color = Color::random_range(0.5,1.0);
+                    let color = Color::from_tuple((rng.gen_range(0.5, 1.0), rng.gen_range(0.5, 1.0), rng.gen_range(0.5, 1.0)));
                     let fuzz = rng.gen_range(0.0, 0.5);
                     world.addMat(name.clone(), matTypes::metal, (color.x, color.y, color.z), fuzz, 1.0);
                     world.addSphere((center.x,center.y,center.z), 0.2, name);
@@ -280,122 +85,492 @@ fn makeWorld<'a>() -> World{
     // world.addTriMesh(triList, "red");
     return world;
 }
-fn bufferIterator<T>(
-    b: &mut u32,
-    idx: u64,
-    width: usize,
-    height: usize,
-    sample_count: u32,
-    scene: &T,
-    cam: &Camera
-)where T:Hittable + Send + Sync {
-   
-    let max_depth = 50;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Instant;
 
-    let i = idx % width as u64;
-    let j = idx / (width) as u64;
-    let mut rng = thread_rng();
-    let mut pixel_color = Color::<f64>::zero();
-    //TODO: MAKE THIS WORK
-    let mut uarr: Vec<f64> =  Vec::new();
-    let mut varr: Vec<f64> =  Vec::new();
-    for k in 0..sample_count {
-        uarr.push((i as f64 + rng.gen_range(0.0, 1.0)) / (width) as f64);
-        varr.push((j as f64 + rng.gen_range(0.0, 1.0)) / (height) as f64);
+fn print_stats(startTime: Instant) {
+    println!("TIME      {}", startTime.elapsed().as_millis());
+    println!("RAY COUNT {}", RAY_COUNT.load(Ordering::Acquire));
+    println!("AVG PATH  {:.2}", average_path_length());
+    println!("TRI COUNT {}", TRI_COUNT.load(Ordering::Acquire));
+}
+
+// Either the tone mapped pixel, from the denoised frame if there is one, or with a heatmap max how
+// many samples it has taken so far
+fn display_pixel(
+    film: &Film,
+    i: usize,
+    tm: &ToneMapping,
+    gains: &Color<f64>,
+    heatmap: Option<u32>,
+    denoised: Option<&Framebuffer>,
+) -> u32 {
+    match (heatmap, denoised) {
+        (Some(max), _) => to_display(heat_color(film.samples[i] as f64 / max as f64)),
+        (None, Some(fb)) => to_display(tm.apply_with_gains(fb.pixels[i], gains)),
+        (None, None) => to_display(tm.apply_with_gains(film.resolve_pixel(i), gains)),
     }
-    pixel_color = (0..sample_count).into_par_iter().map(
-        |k| {
-        let mut u = uarr[k as usize];
-        let mut v = varr[k as usize];
-        let r = cam.get_ray(u, v);
-        raycolor(&r, scene, max_depth)
-        }).sum();
-    let c :Color::<f64> = &pixel_color * (1.0 / sample_count as f64); //divide color by samplect
-    let idx: usize = idx as usize;
-    let color = from_u8_rgb(
-        (255 as f64 * clamp(c.x.sqrt(), 0.0, 1.0)) as u8,
-        (255 as f64 * clamp(c.y.sqrt(), 0.0, 1.0)) as u8,
-        (255 as f64 * clamp(c.z.sqrt(), 0.0, 1.0)) as u8,
-    );
-    *b = from_u8_rgb(
-        (255 as f64 * clamp(c.x.sqrt(), 0.0, 1.0)) as u8,
-        (255 as f64 * clamp(c.y.sqrt(), 0.0, 1.0)) as u8,
-        (255 as f64 * clamp(c.z.sqrt(), 0.0, 1.0)) as u8,
-    );
 }
 
-fn from_u8_rgb(r: u8, g: u8, b: u8) -> u32 {
-    let (r, g, b) = (r as u32, g as u32, b as u32);
-    (r << 16) | (g << 8) | b
+// Where the denoised copy of path goes, next to it
+fn denoised_path(path: &Path) -> std::path::PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("render");
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    path.with_file_name(format!("{}.denoised.{}", stem, ext))
+}
+
+// Corner brackets over a tile a worker just picked up, painted over once it comes back
+fn mark_tile(buffer: &mut [u32], width: usize, tile: &Tile) {
+    let len = 4.min(tile.width).min(tile.height);
+    let (x1, y1) = (tile.x + tile.width - 1, tile.y + tile.height - 1);
+    for i in 0..len {
+        for &(x, y) in [
+            (tile.x + i, tile.y),
+            (tile.x, tile.y + i),
+            (x1 - i, tile.y),
+            (x1, tile.y + i),
+            (tile.x + i, y1),
+            (tile.x, y1 - i),
+            (x1 - i, y1),
+            (x1, y1 - i),
+        ]
+        .iter()
+        {
+            buffer[y * width + x] = 0x00ff_a000;
+        }
+    }
+}
+
+fn save_heatmap(path: &Path, film: &Film, max: u32) {
+    let fb = film.sample_heatmap(max);
+    let saved = match imageio::format_for_path(path) {
+        Some(format) if format.is_hdr() => {
+            imageio::save_hdr(path, fb.width, fb.height, &fb.to_rgb32(), ExrPixelType::Half)
+        }
+        _ => imageio::save_ldr(path, &fb.to_ldr(8, false), &[], 90),
+    };
+    match saved {
+        Ok(()) => println!("wrote {}", path.display()),
+        Err(e) => eprintln!("failed to write {}: {}", path.display(), e),
+    }
+}
+
+// One multi-channel EXR with the beauty's R, G, B and every AOV, always at float precision so the
+// ids come out exact. Any other format gets an image per AOV, path.<aov>.ext: PFMs with the raw
+// values and LDR images squeezed into something to look at
+fn save_aovs(path: &Path, film: &Film, fb: &Framebuffer) {
+    let format = imageio::format_for_path(path);
+    let mut written = vec![];
+    let saved = if format == Some(imageio::ImageFormat::Exr) {
+        let mut channels = imageio::rgb_channels(&fb.to_rgb32());
+        channels.extend(aov::aov_channels(&film.aovs));
+        written.push(path.to_path_buf());
+        imageio::write_exr(path, fb.width, fb.height, &channels, ExrPixelType::Float)
+    } else {
+        Aov::ALL.iter().try_for_each(|a| {
+            let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("aov");
+            let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+            let file = path.with_file_name(format!("{}.{}.{}", stem, a.name(), ext));
+            written.push(file.clone());
+            if format == Some(imageio::ImageFormat::Pfm) {
+                imageio::write_pfm(&file, fb.width, fb.height, &aov::aov_rgb(*a, &film.aovs))
+            } else {
+                let preview = aov::aov_preview(*a, &film.aovs, fb.width, fb.height);
+                imageio::save_ldr(&file, &preview.to_ldr(8, false), &[], 90)
+            }
+        })
+    };
+    match saved {
+        Ok(()) => {
+            for file in written.iter() {
+                println!("wrote {}", file.display());
+            }
+        }
+        Err(e) => eprintln!("failed to write {}: {}", written.last().unwrap().display(), e),
+    }
+}
+
+// LDR output goes through the tone mapping, EXR/PFM keep the linear values
+fn save_image(opts: &cli::Options, fb: &Framebuffer, samples: u32, seed: u64, seconds: f64) {
+    let saved = match imageio::format_for_path(&opts.output) {
+        Some(format) if format.is_hdr() => {
+            imageio::save_hdr(&opts.output, fb.width, fb.height, &fb.to_rgb32(), opts.exr_type)
+        }
+        _ => {
+            let metadata = vec![
+                ("Software".to_string(), "raytracing_one_weekend".to_string()),
+                ("Scene".to_string(), opts.scene.clone()),
+                ("Samples".to_string(), samples.to_string()),
+                ("Seed".to_string(), seed.to_string()),
+                ("Tone mapping".to_string(), describe_tonemap(&opts.tonemap)),
+                ("Render time".to_string(), format!("{:.1}s", seconds)),
+            ];
+            let image = fb.tonemapped(&opts.tonemap).to_ldr(opts.bit_depth, opts.dither);
+            imageio::save_ldr(&opts.output, &image, &metadata, opts.jpeg_quality)
+        }
+    };
+    match saved {
+        Ok(()) => println!("wrote {}", opts.output.display()),
+        Err(e) => {
+            eprintln!("failed to write {}: {}", opts.output.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn describe_tonemap(tm: &ToneMapping) -> String {
+    format!(
+        "{} exposure {:+.1} EV, white point {}, white balance {}K",
+        tm.operator.name(),
+        tm.exposure,
+        tm.white_point,
+        tm.white_balance
+    )
 }
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::{Duration, Instant};
 
 fn main() {
-    // Image
-    let width = 1200 ;
-    let height = 800 ;
-    let samplect = 500;
+    let opts = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if opts.command == cli::Command::Help {
+        print!("{}", cli::USAGE);
+        return;
+    }
+    if let Some(threads) = opts.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("failed to set up the rayon thread pool");
+    }
+    let seed = opts.seed.unwrap_or_else(|| thread_rng().gen());
+    let (world, camDesc, sceneSettings, background) = if cli::SCENES.contains(&opts.scene.as_str()) {
+        (makeWorld(seed), CameraDescription::default(), SettingsDescription::default(), None)
+    } else {
+        let desc = match sceneLoader::load_scene(Path::new(&opts.scene)) {
+            Ok(desc) => desc,
+            Err(e @ SceneError::Io(..)) => {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("error: {}:{}", opts.scene, e);
+                std::process::exit(1);
+            }
+        };
+        match World::from_description(&desc) {
+            Ok(world) => (world, desc.camera, desc.settings, desc.background),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let background = match opts.envmap.clone() {
+        Some(file) => Some(BackgroundDescription::EnvMap { file, rotate: 0.0, intensity: 1.0 }),
+        None => background,
+    };
+    let backgroundName = match &background {
+        None => "gradient (default sky)".to_string(),
+        Some(BackgroundDescription::Solid(c)) => format!("solid {}", c),
+        Some(BackgroundDescription::Gradient { .. }) => "gradient".to_string(),
+        Some(BackgroundDescription::EnvMap { file, .. }) => format!("envmap {}", file.display()),
+    };
+    let background = match background.map(|b| b.build()) {
+        Some(Ok(b)) => Some(b),
+        Some(Err(e)) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    // Image -- command line beats the scene file beats the defaults
+    let settings = RenderSettings {
+        width: opts.width.or(sceneSettings.width).unwrap_or(1200),
+        height: opts.height.or(sceneSettings.height).unwrap_or(800),
+        samples: opts.samples.or(sceneSettings.samples).unwrap_or(500),
+        max_depth: opts.max_depth.or(sceneSettings.max_depth).unwrap_or(50),
+        rr_depth: opts.rr_depth.or(sceneSettings.rr_depth).unwrap_or(3),
+        seed: seed,
+    };
+    let (width, height) = (settings.width, settings.height);
+    let cam = camDesc.build(settings.aspect_ratio());
 
-    let world = makeWorld();
-    let camOrigin = Vector3::from_tuple((10.0,2.0,3.0));
-    let camTgt = Vector3::from_tuple((0.0,0.0,0.0));
-    let cam = Camera::new(camOrigin,camTgt, 30.0, 3.0 / 2.0, 0.1, 10.0 );
-    let scene = bvhNode::create_from_hlist(Arc::new(world.objects)).unwrap();
-    
-    let mut buffer: Vec<u32> = vec![0; width as usize * height as usize];
-    let mut renderbuffer: Vec<u32> = vec![0; width as usize * height as usize];
+    if opts.command == cli::Command::Info {
+        let bbox = Bounds::from_hittables(&world.objects.objects);
+        println!("scene      {}", opts.scene);
+        println!("seed       {}", seed);
+        println!("resolution {}x{}", width, height);
+        println!("samples    {}", settings.samples);
+        println!("max depth  {}", settings.max_depth);
+        println!("rr depth   {}", settings.rr_depth);
+        println!("sampler    {}", opts.sampler.name());
+        println!("filter     {} radius {}", opts.filter.name(), opts.filter_radius.unwrap_or(opts.filter.default_radius()));
+        println!("threads    {}", rayon::current_num_threads());
+        println!("materials  {}", world.materials.len());
+        println!("objects    {}", world.objects.objects.len());
+        println!("triangles  {}", TRI_COUNT.load(Ordering::Acquire));
+        println!("lights     {}", world.lights.lights.len());
+        println!("bounds     {} - {}", bbox.min, bbox.max);
+        println!("background {}", backgroundName);
+        println!("tonemap    {}", describe_tonemap(&opts.tonemap));
+        println!("output     {}", opts.output.display());
+        return;
+    }
+    let mut scene = Scene::new(world, cam);
+    if let Some(background) = background {
+        scene.background = background;
+    }
+    let mut renderer = Renderer::new(settings);
+    renderer.tile_size = opts.tile_size;
+    renderer.tile_order = opts.tile_order;
+    renderer.sampler = opts.sampler;
+    renderer.filter = Filter {
+        kind: opts.filter,
+        radius: opts.filter_radius.unwrap_or(opts.filter.default_radius()),
+    };
+    // the preview records them too so the denoiser can be switched on at any time
+    renderer.aovs = opts.aovs.is_some() || opts.denoise || opts.command == cli::Command::Preview;
+    renderer.adaptive = opts.adaptive.map(|threshold| AdaptiveSampling {
+        threshold: threshold,
+        min_samples: opts.min_samples.min(settings.samples),
+    });
+    let startTime = Instant::now();
+
+    if opts.command == cli::Command::Render {
+        println!("rendering {}x{} at {} spp, seed {}", width, height, settings.samples, seed);
+        let step = (renderer.tiles().len() / 10).max(1);
+        let film = renderer.render_film(&scene, |done, total| {
+            if done % step == 0 || done == total {
+                println!("{}/{} tiles complete", done, total);
+            }
+        });
+        print_stats(startTime);
+        if renderer.adaptive.is_some() {
+            println!("AVG SPP   {:.1}", film.average_samples());
+        }
+        let fb = film.resolve();
+        save_image(&opts, &fb, settings.samples, seed, startTime.elapsed().as_secs_f64());
+        if let Some(path) = &opts.heatmap {
+            save_heatmap(path, &film, settings.samples);
+        }
+        if let Some(path) = &opts.aovs {
+            save_aovs(path, &film, &fb);
+        }
+        if opts.denoise {
+            let started = Instant::now();
+            let denoised = Denoiser::default().denoise(&fb, &film.aovs);
+            println!("DENOISE   {}", started.elapsed().as_millis());
+            let opts = cli::Options { output: denoised_path(&opts.output), ..opts.clone() };
+            save_image(&opts, &denoised, settings.samples, seed, startTime.elapsed().as_secs_f64());
+        }
+        return;
+    }
+
+    // Progressive: every pass adds one sample to each pixel, so the whole frame shows up at 1 spp
+    // and keeps refining. A background thread renders the passes tile by tile and streams them
+    // back here, where the film holds the linear sums so the tone mapping can change any time
+    let tiles_total = renderer.tiles().len();
+    let mut scene = Arc::new(scene);
+    let spawn_worker = |scene: &Arc<Scene>| {
+        let (tx, rx) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (renderer, scene, worker_cancel) = (renderer.clone(), scene.clone(), cancel.clone());
+        let handle = std::thread::spawn(move || renderer.render_progressive(&scene, &tx, &worker_cancel));
+        (rx, cancel, handle)
+    };
+    let mut worker = spawn_worker(&scene);
+    let mut film = Film::new(width, height);
+    let mut tiles_done = 0;
+    let mut started = Instant::now();
+    let mut tonemap = opts.tonemap;
+    let mut gains = tonemap.white_balance_gains();
+    let mut buffer: Vec<u32> = vec![0; width * height];
     let wi = minifb::WindowOptions {
-        borderless: true,
-        title: false,
+        borderless: false,
+        title: true,
         resize: false,
         scale: minifb::Scale::X1,
         topmost: false,
         transparency: false,
         scale_mode: minifb::ScaleMode::Stretch,
     };
-    let mut window =
-        Window::new("test - esc to exit", width as usize, height as usize, wi).unwrap();
+    let mut window = Window::new("raytracing_one_weekend", width as usize, height as usize, wi).unwrap();
     window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
 
-    
-   
-    
-    let size = height * width;
-    let mut iter = buffer.iter_mut();
-    
-    let startTime = Instant::now();
-    let mut i = 0;
     let mut timed = false;
-    while window.is_open() && !window.is_key_down(Key::C) {
-        if i < size {
-            let batch = size /  (2 ^ 16);
-            for j in i..(i + batch) {
-                if j > size - 1 {
-                    break;
+    let mut show_heatmap = false;
+    // The denoised frame is redone whenever the pass count reaches a power of two and at the end,
+    // often enough to follow the render without stalling the window on every pass
+    let mut show_denoised = opts.denoise;
+    let mut denoised: Option<Framebuffer> = None;
+    // Fly camera. Any move rebuilds the camera and starts the accumulation over
+    let mut camDesc = camDesc;
+    let dof_aperture = if camDesc.aperture > 0.0 {
+        camDesc.aperture
+    } else {
+        camDesc.focus_distance / 50.0
+    };
+    let mut last_mouse: Option<(f32, f32)> = None;
+    println!("WASD/QE: fly, drag: orbit, scroll: zoom, F: depth of field, P: print camera");
+    println!("H: sample count heatmap, N: denoise");
+    println!("Enter: save, T: tone operator, -/=: exposure, [/]: white balance, C/Esc: quit");
+    while window.is_open() && !window.is_key_down(Key::C) && !window.is_key_down(Key::Escape) {
+        let cam_before = camDesc;
+        let step = 0.02 * (camDesc.target - camDesc.origin).magnitude();
+        let axis = |pos: Key, neg: Key| {
+            (window.is_key_down(pos) as i32 - window.is_key_down(neg) as i32) as f64 * step
+        };
+        let (forward, right, up) = (axis(Key::W, Key::S), axis(Key::D, Key::A), axis(Key::E, Key::Q));
+        if forward != 0.0 || right != 0.0 || up != 0.0 {
+            camDesc.fly(forward, right, up);
+        }
+        let mouse = window.get_mouse_pos(MouseMode::Discard);
+        if window.get_mouse_down(MouseButton::Left) {
+            if let (Some((x0, y0)), Some((x1, y1))) = (last_mouse, mouse) {
+                if x0 != x1 || y0 != y1 {
+                    camDesc.orbit(0.005 * (x1 - x0) as f64, 0.005 * (y1 - y0) as f64);
+                }
+            }
+            last_mouse = mouse;
+        } else {
+            last_mouse = None;
+        }
+        if let Some((_, scroll)) = window.get_scroll_wheel() {
+            if scroll != 0.0 {
+                camDesc.zoom(0.95f64.powf(scroll as f64));
+            }
+        }
+        if window.is_key_pressed(Key::F, KeyRepeat::No) {
+            camDesc.aperture = if camDesc.aperture > 0.0 { 0.0 } else { dof_aperture };
+            println!("depth of field {}", if camDesc.aperture > 0.0 { "on" } else { "off" });
+        }
+        if camDesc != cam_before {
+            // keep whatever we're looking at in focus
+            camDesc.focus_distance = (camDesc.target - camDesc.origin).magnitude();
+            worker.1.store(true, Ordering::Relaxed);
+            worker.2.join().unwrap();
+            Arc::get_mut(&mut scene).unwrap().camera = camDesc.build(settings.aspect_ratio());
+            worker = spawn_worker(&scene);
+            film = Film::new(width, height);
+            denoised = None;
+            tiles_done = 0;
+            started = Instant::now();
+            timed = false;
+        }
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            println!("{}", camDesc.to_scene_line());
+        }
+
+        let before = tonemap;
+        if window.is_key_pressed(Key::T, KeyRepeat::No) {
+            tonemap.operator = tonemap.operator.next();
+        }
+        if window.is_key_pressed(Key::Equal, KeyRepeat::Yes) {
+            tonemap.exposure += 0.5;
+        }
+        if window.is_key_pressed(Key::Minus, KeyRepeat::Yes) {
+            tonemap.exposure -= 0.5;
+        }
+        if window.is_key_pressed(Key::LeftBracket, KeyRepeat::Yes) {
+            tonemap.white_balance = (tonemap.white_balance - 500.0).max(2000.0);
+        }
+        if window.is_key_pressed(Key::RightBracket, KeyRepeat::Yes) {
+            tonemap.white_balance = (tonemap.white_balance + 500.0).min(25000.0);
+        }
+        let (show_heatmap_before, show_denoised_before) = (show_heatmap, show_denoised);
+        if window.is_key_pressed(Key::H, KeyRepeat::No) {
+            show_heatmap = !show_heatmap;
+        }
+        if window.is_key_pressed(Key::N, KeyRepeat::No) {
+            show_denoised = !show_denoised;
+            println!("denoising {}", if show_denoised { "on" } else { "off" });
+            denoised = if show_denoised && tiles_done >= tiles_total {
+                Some(Denoiser::default().denoise(&film.resolve(), &film.aovs))
+            } else {
+                None
+            };
+        }
+        if tonemap != before || show_heatmap != show_heatmap_before || show_denoised != show_denoised_before {
+            if tonemap != before {
+                println!("{}", describe_tonemap(&tonemap));
+            }
+            gains = tonemap.white_balance_gains();
+            let heatmap = if show_heatmap { Some(settings.samples) } else { None };
+            for (i, p) in buffer.iter_mut().enumerate() {
+                *p = display_pixel(&film, i, &tonemap, &gains, heatmap, denoised.as_ref());
+            }
+        }
+        if window.is_key_pressed(Key::Enter, KeyRepeat::No) {
+            // whatever is there so far, tiles done in the current pass have one sample more than the rest
+            let opts = cli::Options { tonemap: tonemap, ..opts.clone() };
+            let pass = (tiles_done / tiles_total) as u32;
+            let fb = film.resolve();
+            save_image(&opts, &fb, pass, seed, started.elapsed().as_secs_f64());
+            if let (Some(path), false) = (&opts.aovs, film.aovs.is_empty()) {
+                save_aovs(path, &film, &fb);
+            }
+            if let Some(denoised) = &denoised {
+                let opts = cli::Options { output: denoised_path(&opts.output), ..opts.clone() };
+                save_image(&opts, denoised, pass, seed, started.elapsed().as_secs_f64());
+            }
+        }
+
+        // take in whatever the worker finished since the last frame. The denoised view only
+        // changes when a whole pass is done
+        let heatmap = if show_heatmap { Some(settings.samples) } else { None };
+        let tiles_before = tiles_done;
+        while let Ok(event) = worker.0.try_recv() {
+            match event {
+                TileEvent::Started(tile) if !show_denoised => mark_tile(&mut buffer, width, &tile),
+                TileEvent::Started(_) => {}
+                TileEvent::Finished { result, .. } => {
+                    film.add_tile(&result);
+                    tiles_done += 1;
+                    if show_denoised && denoised.is_some() && !show_heatmap {
+                        continue;
+                    }
+                    let tile = result.splat;
+                    for i in 0..tile.pixel_count() {
+                        let p = tile.pixel_index(i, width);
+                        buffer[p] = display_pixel(&film, p, &tonemap, &gains, heatmap, None);
+                    }
                 }
-                bufferIterator(
-                    &mut buffer[(size - 1 - j)],
-                    (j) as u64,
-                    width as usize,
-                    height as usize,
-                    samplect,
-                    &scene,
-                    &cam,
-                );
             }
-            i += batch;
         }
-        if i >= size && !timed {
-            println!("TIME      {}", startTime.elapsed().as_millis());
-            println!("RAY COUNT {}", RAY_COUNT.load(Ordering::Acquire));
-            println!("TRI TESTS {}", RAY_TRI_TESTS.load(Ordering::Acquire));
-            println!("TRI ISECT {}", RAY_TRI_ISECT.load(Ordering::Acquire));
-            println!("TRI COUNT {}", TRI_COUNT.load(Ordering::Acquire));
+        let passes_done = (tiles_done / tiles_total) as u32;
+        if show_denoised
+            && passes_done > (tiles_before / tiles_total) as u32
+            && (passes_done.is_power_of_two() || passes_done == settings.samples)
+        {
+            denoised = Some(Denoiser::default().denoise(&film.resolve(), &film.aovs));
+            for (i, p) in buffer.iter_mut().enumerate() {
+                *p = display_pixel(&film, i, &tonemap, &gains, heatmap, denoised.as_ref());
+            }
+        }
+        let pass = (tiles_done / tiles_total) as u32;
+        if pass == settings.samples && !timed {
+            print_stats(started);
             timed = true;
         }
+        window.set_title(&format!(
+            "{} - {}/{} spp - {:.1}s{}",
+            opts.scene,
+            pass,
+            settings.samples,
+            started.elapsed().as_secs_f64(),
+            if timed { " - done" } else { "" }
+        ));
         window
             .update_with_buffer(&buffer, width as usize, height as usize)
             .unwrap();
     }
+    worker.1.store(true, Ordering::Relaxed);
+    worker.2.join().unwrap();
 }
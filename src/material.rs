@@ -1,9 +1,8 @@
-use crate::hit::{HitInfo, Hittable, Tri};
+use crate::hit::HitInfo;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::vectors::Vector3;
 use crate::vectors::Vector3 as Color;
-use rand::prelude::*;
-use std::cmp;
 
 pub fn schlick(cos: f64, index: f64) -> f64 {
     let mut r0 = (1.0 - index) / (1.0 + index);
@@ -12,11 +11,37 @@ pub fn schlick(cos: f64, index: f64) -> f64 {
 }
 
 pub trait Material {
-    fn scatter(&self, r: &Ray, hit: &HitInfo) -> Option<scatter_result>; // DOES THIS NEED TO RETURN SCATTERED INSTEAD?
+    fn scatter(&self, r: &Ray, hit: &HitInfo, sampler: &mut dyn Sampler) -> Option<scatter_result>; // DOES THIS NEED TO RETURN SCATTERED INSTEAD?
+    // Radiance leaving the surface towards r.origin, added on top of whatever scatter() brings back
+    fn emitted(&self, _r: &Ray, _hit: &HitInfo) -> Color<f64> {
+        Color::zero()
+    }
+    // BSDF * cos towards the unit direction wi. Delta lobes (mirrors, glass) can't be hit by a
+    // direction picked from outside, so they contribute nothing here and scatter() flags them instead
+    fn eval(&self, _r: &Ray, _hit: &HitInfo, _wi: &Vector3<f64>) -> Color<f64> {
+        Color::zero()
+    }
+    // Solid angle density of scatter() picking the unit direction wi, ignoring delta lobes
+    fn pdf(&self, _r: &Ray, _hit: &HitInfo, _wi: &Vector3<f64>) -> f64 {
+        0.0
+    }
+    // Emissive surfaces go in the world's light list
+    fn is_light(&self) -> bool {
+        false
+    }
+    // The surface's color with the lighting taken out, for the albedo AOV
+    fn albedo(&self, hit: &HitInfo) -> Color<f64>;
 }
+// attenuation is eval / pdf for the sampled direction. Delta lobes have no meaningful pdf,
+// so they set specular and leave pdf at 0
 pub struct scatter_result {
     pub attenuation: Color<f64>,
     pub ray: Ray,
+    pub pdf: f64,
+    pub specular: bool,
+}
+fn cosine_pdf(n: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+    n.dot(wi).max(0.0) / std::f64::consts::PI
 }
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Lambert {
@@ -24,43 +49,69 @@ pub struct Lambert {
 }
 
 impl Material for Lambert {
-    fn scatter(&self, r: &Ray, hit: &HitInfo) -> Option<scatter_result> {
-        let scatter_direction = hit.normal + Vector3::<f64>::random_unit_vector();
+    fn scatter(&self, _r: &Ray, hit: &HitInfo, sampler: &mut dyn Sampler) -> Option<scatter_result> {
+        let scatter_direction = hit.normal + Vector3::<f64>::unit_vector_from(sampler.get_2d());
         let result_scattered = Ray::new(hit.p, scatter_direction);
         let result_attennuation = self.albedo; // Need to manually copy/clone?
         return Some(scatter_result {
             attenuation: result_attennuation,
+            pdf: cosine_pdf(&hit.normal, &scatter_direction.normalized()),
             ray: result_scattered,
+            specular: false,
         });
     }
+    fn eval(&self, _r: &Ray, hit: &HitInfo, wi: &Vector3<f64>) -> Color<f64> {
+        &self.albedo * cosine_pdf(&hit.normal, wi)
+    }
+    fn pdf(&self, _r: &Ray, hit: &HitInfo, wi: &Vector3<f64>) -> f64 {
+        cosine_pdf(&hit.normal, wi)
+    }
+    fn albedo(&self, _hit: &HitInfo) -> Color<f64> {
+        self.albedo
+    }
 }
+// Area light -- emits albedo * emission from both faces and absorbs everything that hits it
 pub struct Emissive {
     pub albedo: Color<f64>,
     pub emission: f64,
 }
 impl Material for Emissive {
-    fn scatter(&self, r: &Ray, hit: &HitInfo) -> Option<scatter_result> {
-        let scatter_direction = Vector3::<f64>::random_unit_vector();
-        let result_scattered = Ray::new(hit.p, scatter_direction);
-        let result_attennuation = self.emission * self.albedo; // Need to manually copy/clone?
-        return Some(scatter_result {
-            attenuation: result_attennuation,
-            ray: result_scattered,
-        });
+    fn scatter(&self, _r: &Ray, _hit: &HitInfo, _sampler: &mut dyn Sampler) -> Option<scatter_result> {
+        None
+    }
+    fn emitted(&self, _r: &Ray, _hit: &HitInfo) -> Color<f64> {
+        self.emission * self.albedo
+    }
+    fn is_light(&self) -> bool {
+        self.emission > 0.0
+    }
+    fn albedo(&self, _hit: &HitInfo) -> Color<f64> {
+        self.albedo
     }
 }
 pub struct Normal {}
 
 impl Material for Normal {
-    fn scatter(&self, r: &Ray, hit: &HitInfo) -> Option<scatter_result> {
-        let scatter_direction = hit.normal + Vector3::<f64>::random_unit_vector();
+    fn scatter(&self, _r: &Ray, hit: &HitInfo, sampler: &mut dyn Sampler) -> Option<scatter_result> {
+        let scatter_direction = hit.normal + Vector3::<f64>::unit_vector_from(sampler.get_2d());
         let result_scattered = Ray::new(hit.p, scatter_direction);
         let result_attennuation = hit.normal; // Need to manually copy/clone?
         return Some(scatter_result {
             attenuation: result_attennuation,
+            pdf: cosine_pdf(&hit.normal, &scatter_direction.normalized()),
             ray: result_scattered,
+            specular: false,
         });
     }
+    fn eval(&self, _r: &Ray, hit: &HitInfo, wi: &Vector3<f64>) -> Color<f64> {
+        &hit.normal * cosine_pdf(&hit.normal, wi)
+    }
+    fn pdf(&self, _r: &Ray, hit: &HitInfo, wi: &Vector3<f64>) -> f64 {
+        cosine_pdf(&hit.normal, wi)
+    }
+    fn albedo(&self, hit: &HitInfo) -> Color<f64> {
+        hit.normal
+    }
 }
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Metal {
@@ -68,24 +119,54 @@ pub struct Metal {
     pub fuzz: f64,
 }
 
+impl Metal {
+    // scatter() picks reflect + fuzz * (point in the unit ball), so the density of a direction is the
+    // share of the fuzz ball's volume along that ray, weighted by t^2 for the solid angle
+    fn fuzz_pdf(&self, r: &Ray, hit: &HitInfo, wi: &Vector3<f64>) -> f64 {
+        if self.fuzz <= 0.0 || hit.normal.dot(wi) <= 0.0 {
+            return 0.0;
+        }
+        let reflected = Vector3::<f64>::reflect(r.dir.normalized(), hit.normal);
+        let b = wi.dot(&reflected);
+        let disc = b * b - (1.0 - self.fuzz * self.fuzz);
+        if disc <= 0.0 {
+            return 0.0;
+        }
+        let t0 = (b - disc.sqrt()).max(0.0);
+        let t1 = b + disc.sqrt();
+        if t1 <= 0.0 {
+            return 0.0;
+        }
+        (t1.powi(3) - t0.powi(3)) / (4.0 * std::f64::consts::PI * self.fuzz.powi(3))
+    }
+}
 impl Material for Metal {
-    fn scatter(&self, r: &Ray, hit: &HitInfo) -> Option<scatter_result> {
+    fn scatter(&self, r: &Ray, hit: &HitInfo, sampler: &mut dyn Sampler) -> Option<scatter_result> {
         let scatter_direction = Vector3::<f64>::reflect(r.dir.normalized(), hit.normal);
-        let result_scattered = Ray::new(
-            hit.p,
-            scatter_direction + (&Vector3::<f64>::random_in_unitsphere() * self.fuzz),
-        );
+        let fuzzed = scatter_direction + (&Vector3::<f64>::in_unit_ball_from(sampler.get_2d(), sampler.get_1d()) * self.fuzz);
         let result_attennuation = self.albedo; // Need to manually copy/clone?
-        match scatter_direction.dot(&hit.normal) > 0.0 {
+        // anything fuzzed below the surface is absorbed
+        match fuzzed.dot(&hit.normal) > 0.0 {
             true => {
                 return Some(scatter_result {
                     attenuation: result_attennuation,
-                    ray: result_scattered,
+                    pdf: self.fuzz_pdf(r, hit, &fuzzed.normalized()),
+                    ray: Ray::new(hit.p, fuzzed),
+                    specular: self.fuzz <= 0.0,
                 })
             }
             false => return None,
         }
     }
+    fn eval(&self, r: &Ray, hit: &HitInfo, wi: &Vector3<f64>) -> Color<f64> {
+        &self.albedo * self.fuzz_pdf(r, hit, wi)
+    }
+    fn pdf(&self, r: &Ray, hit: &HitInfo, wi: &Vector3<f64>) -> f64 {
+        self.fuzz_pdf(r, hit, wi)
+    }
+    fn albedo(&self, _hit: &HitInfo) -> Color<f64> {
+        self.albedo
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -104,8 +185,9 @@ fn clamp(x: f64, min: f64, max: f64) -> f64 {
     return x;
 }
 //Added some stupid  fuzz and color stuff here -- remember to remove later on if it causes trouble
+// Both lobes count as delta even with fuzz, so glass never samples lights and always sees them in full
 impl Material for Dialectric {
-    fn scatter(&self, r: &Ray, hit: &HitInfo) -> Option<scatter_result> {
+    fn scatter(&self, r: &Ray, hit: &HitInfo, sampler: &mut dyn Sampler) -> Option<scatter_result> {
         let result_attennuation = Color {
             x: 1.0,
             y: 1.0,
@@ -125,29 +207,31 @@ impl Material for Dialectric {
                 let scatter_direction = Vector3::<f64>::reflect(unit_dir, hit.normal);
                 let result_ray = Ray::new(
                     hit.p,
-                    scatter_direction + (&Vector3::<f64>::random_in_unitsphere() * self.fuzz),
+                    scatter_direction + (&Vector3::<f64>::in_unit_ball_from(sampler.get_2d(), sampler.get_1d()) * self.fuzz),
                 );
 
                 return Some(scatter_result {
                     attenuation: result_attennuation,
                     ray: result_ray,
+                    pdf: 0.0,
+                    specular: true,
                 });
             }
             false => {
-                let mut rng = thread_rng();
-
-                match schlick(cos_theta, index) > rng.gen_range(0.0, 1.0) {
+                match schlick(cos_theta, index) > sampler.get_1d() {
                     true => {
                         let result_attennuation = self.albedo;
                         let scatter_direction = Vector3::<f64>::reflect(unit_dir, hit.normal);
                         let result_ray = Ray::new(
                             hit.p,
                             scatter_direction
-                                + (&Vector3::<f64>::random_in_unitsphere() * self.fuzz),
+                                + (&Vector3::<f64>::in_unit_ball_from(sampler.get_2d(), sampler.get_1d()) * self.fuzz),
                         );
                         return Some(scatter_result {
                             attenuation: result_attennuation,
                             ray: result_ray,
+                            pdf: 0.0,
+                            specular: true,
                         });
                     }
 
@@ -160,11 +244,13 @@ impl Material for Dialectric {
                         let result_ray = Ray::new(
                             hit.p,
                             scatter_direction
-                                - (&Vector3::<f64>::random_in_unitsphere() * self.fuzz),
+                                - (&Vector3::<f64>::in_unit_ball_from(sampler.get_2d(), sampler.get_1d()) * self.fuzz),
                         );
                         return Some(scatter_result {
                             attenuation: result_attennuation,
                             ray: result_ray,
+                            pdf: 0.0,
+                            specular: true,
                         });
                     }
                 }
@@ -181,4 +267,70 @@ impl Material for Dialectric {
         //     false => return None,
         // }
     }
+    fn albedo(&self, _hit: &HitInfo) -> Color<f64> {
+        self.albedo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Pcg32;
+    use std::sync::Arc;
+
+    // Lights glow the same from either side and absorb whatever hits them
+    #[test]
+    fn emissive_emits_from_both_faces_and_never_scatters() {
+        let light = Arc::new(Emissive {
+            albedo: Color::from_tuple((1.0, 0.5, 0.25)),
+            emission: 4.0,
+        });
+        let mat: Arc<dyn Material + Send + Sync> = light.clone();
+        let r = Ray::new(Vector3::from_tuple((0.0, 1.0, 0.0)), Vector3::from_tuple((0.0, -1.0, 0.0)));
+        let mut rng = Pcg32::new(1, 2);
+        let mut sampler = crate::sampler::SamplerKind::Independent.create(1, 1);
+        for &front_face in [true, false].iter() {
+            let hit = HitInfo {
+                t: 1.0,
+                p: Vector3::zero(),
+                normal: Vector3::up(),
+                front_face: front_face,
+                uv: Vector3::zero(),
+                mat: &mat,
+                object_id: 0,
+            };
+            assert_eq!(light.emitted(&r, &hit), Color::from_tuple((4.0, 2.0, 1.0)));
+            assert!(light.scatter(&r, &hit, &mut *sampler).is_none());
+            let wi = Vector3::<f64>::random_unit_vector(&mut rng);
+            assert_eq!(light.eval(&r, &hit, &wi), Color::zero());
+        }
+        assert!(light.is_light());
+        assert!(!Emissive { albedo: light.albedo, emission: 0.0 }.is_light());
+    }
+
+    #[test]
+    fn fuzzy_metal_pdf_integrates_to_one() {
+        let metal = Metal {
+            albedo: Color::one(),
+            fuzz: 0.3,
+        };
+        let mat: Arc<dyn Material + Send + Sync> = Arc::new(metal);
+        let hit = HitInfo {
+            t: 1.0,
+            p: Vector3::zero(),
+            normal: Vector3::up(),
+            front_face: true,
+            uv: Vector3::zero(),
+            mat: &mat,
+            object_id: 0,
+        };
+        let r = Ray::new(Vector3::from_tuple((-0.5, 1.0, 0.0)), Vector3::from_tuple((0.5, -1.0, 0.0)));
+        let mut rng = Pcg32::new(3, 4);
+        let n = 1000000;
+        let total: f64 = (0..n)
+            .map(|_| metal.pdf(&r, &hit, &Vector3::<f64>::random_unit_vector(&mut rng)))
+            .sum();
+        let integral = total * 4.0 * std::f64::consts::PI / n as f64;
+        assert!((integral - 1.0).abs() < 0.05, "integral {}", integral);
+    }
 }
@@ -0,0 +1,474 @@
+use crate::hit::{hit_triangle, HitInfo, Hittable, TriHit};
+use crate::material::Material;
+use crate::objLoader::{face_normal, TriData};
+use crate::ray::Ray;
+use crate::transform::Transform;
+use crate::vectors::Vector3;
+use crate::BVH::Bounds;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const LEAF_SIZE: usize = 4;
+const MAX_DEPTH: usize = 60; // keeps the traversal stack a fixed size
+
+// Triangles sharing their vertices. positions, normals, uvs and tangents are parallel arrays with one
+// entry per vertex, left empty when the mesh doesn't have that attribute, and indices holds three
+// vertices per triangle. Meshes without normals are flat shaded. The mesh keeps its own BVH over its
+// triangles, so it goes into the world as a single object
+pub struct TriangleMesh {
+    pub positions: Vec<Vector3<f64>>,
+    pub normals: Vec<Vector3<f64>>,
+    pub uvs: Vec<Vector3<f64>>,
+    pub tangents: Vec<Vector3<f64>>,
+    pub indices: Vec<u32>,
+    pub mat: Arc<dyn Material + Send + Sync>,
+    pub id: u32,
+    // only hit from the side the winding faces, counter-clockwise seen from the front
+    pub cull_backfaces: bool,
+    nodes: Vec<MeshNode>,
+    order: Vec<u32>, // triangle numbers, arranged so every leaf's are a contiguous run
+    bbox: Bounds,
+}
+
+// Leaves have count > 0 and hold order[first..first + count]. Interior nodes have their left child
+// right after them and their right child at first, split along axis
+struct MeshNode {
+    min: Vector3<f64>,
+    max: Vector3<f64>,
+    first: u32,
+    count: u32,
+    axis: u8,
+}
+
+impl TriangleMesh {
+    // Checks the arrays fit together before building the BVH over them
+    pub fn new(
+        positions: Vec<Vector3<f64>>,
+        normals: Vec<Vector3<f64>>,
+        uvs: Vec<Vector3<f64>>,
+        indices: Vec<u32>,
+        mat: Arc<dyn Material + Send + Sync>,
+        id: u32,
+        cull_backfaces: bool,
+    ) -> Result<TriangleMesh, String> {
+        if indices.len() % 3 != 0 {
+            return Err(format!("{} indices, they should come in threes", indices.len()));
+        }
+        for (name, attribute) in [("normals", &normals), ("uvs", &uvs)].iter() {
+            if !attribute.is_empty() && attribute.len() != positions.len() {
+                return Err(format!("{} {} for {} positions", attribute.len(), name, positions.len()));
+            }
+        }
+        if let Some(i) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+            return Err(format!("index {} out of range for {} positions", i, positions.len()));
+        }
+        let mut mesh = TriangleMesh {
+            positions,
+            normals,
+            uvs,
+            tangents: vec![],
+            indices,
+            mat,
+            id,
+            cull_backfaces,
+            nodes: vec![],
+            order: vec![],
+            bbox: Bounds::new(),
+        };
+        mesh.bbox.fitPoints(mesh.positions.iter().cloned());
+        mesh.build_bvh();
+        Ok(mesh)
+    }
+
+    // Welds the loader's triangles back into shared vertices, ones that end up with the same
+    // position, uv and normal once transformed. Triangles without normals get their face normal
+    // at every corner, unless none of them have any and the whole mesh is left flat
+    pub fn from_tris(
+        tris: &[TriData],
+        xform: &Transform,
+        mat: Arc<dyn Material + Send + Sync>,
+        id: u32,
+        cull_backfaces: bool,
+    ) -> TriangleMesh {
+        let zero = Vector3::zero();
+        let has_normals = tris.iter().any(|t| t.has_normals());
+        let has_uvs = tris.iter().any(|t| t.vt0 != zero || t.vt1 != zero || t.vt2 != zero);
+        let bits = |v: &Vector3<f64>| [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()];
+        let mut welded: HashMap<[[u64; 3]; 3], u32> = HashMap::new();
+        let (mut positions, mut normals, mut uvs, mut indices) = (vec![], vec![], vec![], vec![]);
+        for t in tris {
+            let p = [xform.apply_point(t.v0), xform.apply_point(t.v1), xform.apply_point(t.v2)];
+            let n = if !has_normals {
+                [zero; 3]
+            } else if t.has_normals() {
+                [xform.apply_normal(t.vn0), xform.apply_normal(t.vn1), xform.apply_normal(t.vn2)]
+            } else {
+                [face_normal(&p[0], &p[1], &p[2]); 3]
+            };
+            let uv = [t.vt0, t.vt1, t.vt2];
+            for c in 0..3 {
+                let next = positions.len() as u32;
+                let i = *welded.entry([bits(&p[c]), bits(&n[c]), bits(&uv[c])]).or_insert(next);
+                if i == next {
+                    positions.push(p[c]);
+                    if has_normals {
+                        normals.push(n[c]);
+                    }
+                    if has_uvs {
+                        uvs.push(uv[c]);
+                    }
+                }
+                indices.push(i);
+            }
+        }
+        // every index was handed out as a vertex got pushed, so the arrays always line up
+        TriangleMesh::new(positions, normals, uvs, indices, mat, id, cull_backfaces).unwrap()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+    pub fn corners(&self, tri: u32) -> [usize; 3] {
+        let i = tri as usize * 3;
+        [
+            self.indices[i] as usize,
+            self.indices[i + 1] as usize,
+            self.indices[i + 2] as usize,
+        ]
+    }
+
+    // Per vertex tangents pointing along increasing u, for shading that needs a frame lined up with
+    // the texture. Meshes without uvs don't get any
+    pub fn generate_tangents(&mut self) {
+        self.tangents.clear();
+        if self.uvs.is_empty() {
+            return;
+        }
+        let mut tangents = vec![Vector3::zero(); self.positions.len()];
+        let mut face_normals = vec![Vector3::zero(); self.positions.len()];
+        for tri in 0..self.triangle_count() as u32 {
+            let [i0, i1, i2] = self.corners(tri);
+            let e1 = self.positions[i1] - self.positions[i0];
+            let e2 = self.positions[i2] - self.positions[i0];
+            let d1 = self.uvs[i1] - self.uvs[i0];
+            let d2 = self.uvs[i2] - self.uvs[i0];
+            let det = d1.x * d2.y - d2.x * d1.y;
+            if det.abs() < 1e-12 {
+                continue;
+            }
+            let t = (d2.y * e1 - d1.y * e2) / det;
+            let n = e1.cross(&e2);
+            for &i in [i0, i1, i2].iter() {
+                tangents[i] = tangents[i] + t;
+                face_normals[i] = face_normals[i] + n;
+            }
+        }
+        // made perpendicular to the vertex normal
+        self.tangents = (0..self.positions.len())
+            .map(|i| {
+                let n = if self.normals.is_empty() {
+                    face_normals[i].normalized()
+                } else {
+                    self.normals[i]
+                };
+                (tangents[i] - tangents[i].dot(&n) * n).normalized()
+            })
+            .collect();
+    }
+
+    fn hit_tri(&self, tri: u32, r: &Ray, t_min: f64, t_max: f64) -> Option<TriHit> {
+        let [i0, i1, i2] = self.corners(tri);
+        let p = &self.positions;
+        hit_triangle(&p[i0], &p[i1], &p[i2], r, t_min, t_max, self.cull_backfaces)
+    }
+    fn hit_info(&self, r: &Ray, hit: &TriHit, tri: u32) -> HitInfo {
+        let [i0, i1, i2] = self.corners(tri);
+        let zero = Vector3::zero();
+        HitInfo {
+            t: hit.t,
+            p: r.at(hit.t),
+            normal: if self.normals.is_empty() {
+                hit.shading_normal(&zero, &zero, &zero)
+            } else {
+                hit.shading_normal(&self.normals[i0], &self.normals[i1], &self.normals[i2])
+            },
+            front_face: hit.front_face,
+            uv: if self.uvs.is_empty() {
+                zero
+            } else {
+                hit.interpolate(&self.uvs[i0], &self.uvs[i1], &self.uvs[i2])
+            },
+            mat: &self.mat,
+            object_id: self.id,
+        }
+    }
+
+    fn build_bvh(&mut self) {
+        let n = self.triangle_count();
+        let mut boxes = Vec::with_capacity(n);
+        let mut centers = Vec::with_capacity(n);
+        for tri in 0..n as u32 {
+            let [i0, i1, i2] = self.corners(tri);
+            let mut b = Bounds::new();
+            b.fitPoints([i0, i1, i2].iter().map(|&i| self.positions[i]));
+            centers.push(&(b.min + b.max) * 0.5);
+            boxes.push(b);
+        }
+        self.order = (0..n as u32).collect();
+        self.nodes.clear();
+        if n > 0 {
+            self.build_node(&boxes, &centers, 0, n, 0);
+        }
+    }
+
+    // Splits order[start..end] at the middle of its centers' extent along the longest axis, or in
+    // half by count when the middle doesn't separate anything
+    fn build_node(&mut self, boxes: &[Bounds], centers: &[Vector3<f64>], start: usize, end: usize, depth: usize) {
+        let (mut lo, mut hi) = (Vector3::<f64>::inf(), Vector3::<f64>::neg_inf());
+        let (mut clo, mut chi) = (Vector3::<f64>::inf(), Vector3::<f64>::neg_inf());
+        for &tri in self.order[start..end].iter() {
+            let (b, c) = (&boxes[tri as usize], &centers[tri as usize]);
+            lo = Vector3::from_tuple((lo.x.min(b.min.x), lo.y.min(b.min.y), lo.z.min(b.min.z)));
+            hi = Vector3::from_tuple((hi.x.max(b.max.x), hi.y.max(b.max.y), hi.z.max(b.max.z)));
+            clo = Vector3::from_tuple((clo.x.min(c.x), clo.y.min(c.y), clo.z.min(c.z)));
+            chi = Vector3::from_tuple((chi.x.max(c.x), chi.y.max(c.y), chi.z.max(c.z)));
+        }
+        let extent = chi - clo;
+        if end - start <= LEAF_SIZE || depth >= MAX_DEPTH || extent.sqrmagnitude() == 0.0 {
+            self.nodes.push(MeshNode {
+                min: lo,
+                max: hi,
+                first: start as u32,
+                count: (end - start) as u32,
+                axis: 0,
+            });
+            return;
+        }
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let key = |c: &Vector3<f64>| match axis {
+            0 => c.x,
+            1 => c.y,
+            _ => c.z,
+        };
+        let middle = 0.5 * (key(&clo) + key(&chi));
+        let tris = &mut self.order[start..end];
+        tris.sort_by(|a, b| key(&centers[*a as usize]).partial_cmp(&key(&centers[*b as usize])).unwrap());
+        let mut split = tris.partition_point(|t| key(&centers[*t as usize]) < middle);
+        if split == 0 || split == tris.len() {
+            split = tris.len() / 2;
+        }
+        // first is filled in once the left subtree is built
+        let node = self.nodes.len();
+        self.nodes.push(MeshNode {
+            min: lo,
+            max: hi,
+            first: 0,
+            count: 0,
+            axis: axis,
+        });
+        self.build_node(boxes, centers, start, start + split, depth + 1);
+        let right = self.nodes.len() as u32;
+        self.build_node(boxes, centers, start + split, end, depth + 1);
+        self.nodes[node].first = right;
+    }
+}
+
+// Slab test against [t_min, t_max], inv_dir being 1 / dir
+fn hits_box(min: &Vector3<f64>, max: &Vector3<f64>, r: &Ray, inv_dir: &Vector3<f64>, t_min: f64, t_max: f64) -> bool {
+    let (mut near, mut far) = (t_min, t_max);
+    for &(lo, hi, o, inv) in [
+        (min.x, max.x, r.origin.x, inv_dir.x),
+        (min.y, max.y, r.origin.y, inv_dir.y),
+        (min.z, max.z, r.origin.z, inv_dir.z),
+    ]
+    .iter()
+    {
+        let (t0, t1) = ((lo - o) * inv, (hi - o) * inv);
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+    }
+    // a ray parallel to a slab it's outside of gives near = infinity
+    near <= far && near < std::f64::INFINITY
+}
+
+impl Hittable for TriangleMesh {
+    fn get_bounds(&self) -> &Bounds {
+        &self.bbox
+    }
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitInfo> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        // the ray keeps 1 / -dir
+        let inv_dir = &r.invDir * -1.0;
+        let dir_positive = [r.dir.x > 0.0, r.dir.y > 0.0, r.dir.z > 0.0];
+        let mut stack = [0u32; MAX_DEPTH + 2];
+        let mut top = 1;
+        let mut closest = t_max;
+        let mut best: Option<(TriHit, u32)> = None;
+        while top > 0 {
+            top -= 1;
+            let index = stack[top] as usize;
+            let node = &self.nodes[index];
+            if !hits_box(&node.min, &node.max, r, &inv_dir, t_min, closest) {
+                continue;
+            }
+            if node.count > 0 {
+                let first = node.first as usize;
+                for &tri in self.order[first..first + node.count as usize].iter() {
+                    if let Some(hit) = self.hit_tri(tri, r, t_min, closest) {
+                        closest = hit.t;
+                        best = Some((hit, tri));
+                    }
+                }
+            } else {
+                // the nearer child goes on top, so it's opened first and can shorten closest
+                let (left, right) = ((index + 1) as u32, node.first);
+                let (near, far) = if dir_positive[node.axis as usize] { (left, right) } else { (right, left) };
+                stack[top] = far;
+                stack[top + 1] = near;
+                top += 2;
+            }
+        }
+        best.map(|(hit, tri)| self.hit_info(r, &hit, tri))
+    }
+}
+
+// One triangle of a mesh as a Hittable of its own: just the mesh, which triangle and its bounds
+pub struct MeshTriangle {
+    pub mesh: Arc<TriangleMesh>,
+    pub index: u32,
+    bbox: Bounds,
+}
+impl MeshTriangle {
+    pub fn new(mesh: Arc<TriangleMesh>, index: u32) -> MeshTriangle {
+        let mut bbox = Bounds::new();
+        bbox.fitPoints(mesh.corners(index).iter().map(|&i| mesh.positions[i]));
+        MeshTriangle { mesh, index, bbox }
+    }
+}
+impl Hittable for MeshTriangle {
+    fn get_bounds(&self) -> &Bounds {
+        &self.bbox
+    }
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitInfo> {
+        let hit = self.mesh.hit_tri(self.index, r, t_min, t_max)?;
+        Some(self.mesh.hit_info(r, &hit, self.index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambert;
+    use crate::rng::Pcg32;
+    use crate::vectors::Vector3 as Color;
+    use rand::Rng;
+
+    fn mat() -> Arc<dyn Material + Send + Sync> {
+        Arc::new(Lambert { albedo: Color::one() })
+    }
+    fn tri(p: [(f64, f64, f64); 3]) -> TriData {
+        let uv = |q: (f64, f64, f64)| Vector3::from_tuple((q.0, q.1, 0.0));
+        TriData {
+            v0: Vector3::from_tuple(p[0]),
+            v1: Vector3::from_tuple(p[1]),
+            v2: Vector3::from_tuple(p[2]),
+            vt0: uv(p[0]),
+            vt1: uv(p[1]),
+            vt2: uv(p[2]),
+            vn0: Vector3::zero(),
+            vn1: Vector3::zero(),
+            vn2: Vector3::zero(),
+        }
+    }
+
+    // A unit square in z = 0 facing +z, as two triangles the way a loader hands them over
+    #[test]
+    fn shares_vertices_between_triangles() {
+        let quad = vec![
+            tri([(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (1.0, 1.0, 0.0)]),
+            tri([(0.0, 0.0, 0.0), (1.0, 1.0, 0.0), (0.0, 1.0, 0.0)]),
+        ];
+        let mut mesh = TriangleMesh::from_tris(&quad, &Transform::default(), mat(), 2, false);
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert!(mesh.normals.is_empty());
+        assert_eq!(mesh.uvs.len(), 4);
+
+        let r = Ray::new(Vector3::from_tuple((0.25, 0.75, 1.0)), Vector3::from_tuple((0.0, 0.0, -1.0)));
+        let hit = mesh.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(hit.t, 1.0);
+        assert_eq!(hit.normal, Vector3::from_tuple((0.0, 0.0, 1.0)));
+        assert_eq!(hit.uv, Vector3::from_tuple((0.25, 0.75, 0.0)));
+        assert_eq!(hit.object_id, 2);
+
+        mesh.generate_tangents();
+        assert!(mesh.tangents.iter().all(|t| *t == Vector3::right()));
+    }
+
+    #[test]
+    fn new_rejects_mismatched_arrays() {
+        let square = || vec![Vector3::zero(), Vector3::right(), Vector3::up(), Vector3::one()];
+        let make = |normals: usize, indices: Vec<u32>| {
+            TriangleMesh::new(square(), vec![Vector3::up(); normals], vec![], indices, mat(), 0, false)
+        };
+        assert!(make(4, vec![0, 1, 2, 2, 1, 3]).is_ok());
+        assert!(make(0, vec![0, 1, 2]).is_ok());
+        assert_eq!(make(0, vec![0, 1, 2, 3]).err().unwrap(), "4 indices, they should come in threes");
+        assert_eq!(make(3, vec![0, 1, 2]).err().unwrap(), "3 normals for 4 positions");
+        assert_eq!(make(0, vec![0, 1, 4]).err().unwrap(), "index 4 out of range for 4 positions");
+    }
+
+    // A lumpy tessellated sphere, traced through the BVH and one triangle at a time
+    #[test]
+    fn bvh_finds_the_closest_triangle() {
+        let (rings, segments) = (24, 48);
+        let point = |i: usize, j: usize| {
+            let theta = std::f64::consts::PI * i as f64 / rings as f64;
+            let phi = 2.0 * std::f64::consts::PI * j as f64 / segments as f64;
+            let r = 1.0 + 0.2 * (3.0 * phi).sin() * theta.sin();
+            Vector3::from_tuple((r * theta.sin() * phi.cos(), r * theta.cos(), r * theta.sin() * phi.sin()))
+        };
+        let (mut positions, mut indices) = (vec![], vec![]);
+        for i in 0..=rings {
+            for j in 0..segments {
+                positions.push(point(i, j));
+            }
+        }
+        for i in 0..rings as u32 {
+            for j in 0..segments as u32 {
+                let (a, b) = (i * segments as u32 + j, i * segments as u32 + (j + 1) % segments as u32);
+                let (c, d) = (a + segments as u32, b + segments as u32);
+                indices.extend_from_slice(&[a, c, b, b, c, d]);
+            }
+        }
+        let mesh = Arc::new(TriangleMesh::new(positions, vec![], vec![], indices, mat(), 0, false).unwrap());
+        let triangles: Vec<MeshTriangle> = (0..mesh.triangle_count() as u32)
+            .map(|i| MeshTriangle::new(Arc::clone(&mesh), i))
+            .collect();
+        let mut rng = Pcg32::new(3, 0);
+        let mut hits = 0;
+        for _ in 0..500 {
+            let origin = Vector3::from_tuple((rng.gen_range(-2.0, 2.0), rng.gen_range(-2.0, 2.0), 3.0));
+            let target = Vector3::from_tuple((rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0)));
+            let r = Ray::new(origin, target - origin);
+            let mut closest: Option<f64> = None;
+            for t in triangles.iter() {
+                if let Some(h) = t.hit(&r, 0.001, closest.unwrap_or(f64::INFINITY)) {
+                    closest = Some(h.t);
+                }
+            }
+            let found = mesh.hit(&r, 0.001, f64::INFINITY).map(|h| h.t);
+            assert_eq!(found, closest);
+            hits += found.is_some() as u32;
+        }
+        assert!(hits > 100, "{}", hits);
+    }
+}
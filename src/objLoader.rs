@@ -1,54 +1,74 @@
 use crate::vectors::Vector3;
-use rand::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::io;
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::path::Path;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::vec::Vec;
 
-pub fn objToTrilistold() -> Result<Vec<Vec<Vec<f64>>>, Box<dyn std::error::Error>> {
-    let path = Path::new("./src/blendermonkey.obj");
-    let mut i = 0;
-    let mut points = Vec::new();
-    let mut tris: Vec<Vec<Vec<f64>>> = Vec::new(); //<((f64, f64, f64), (f64, f64, f64), (f64, f64, f64))>; //make this work
-    let f = File::open(path)?;
-    let mut reader = BufReader::new(&f);
-    let mut i = 0;
-    let mut line = String::new();
-    loop {
-        line.clear();
-        let result = reader.read_line(&mut line)?;
-        if result == 0 {
-            break;
-        }
-        match &line[0..2] {
-            "v " => {
-                let mut iter = line.split_whitespace();
-                assert_eq!(Some("v"), iter.next()); // Consume prefix
-                let mut position = vec![0.0, 0.0, 0.0];
-                position[0] = iter.next().unwrap().parse()?;
-                position[1] = iter.next().unwrap().parse()?;
-                position[2] = iter.next().unwrap().parse()?;
-                points.push(position);
-            }
-            "f " => {
-                let mut iter = line.split_whitespace();
-                assert_eq!(Some("f"), iter.next()); // Consume prefix
-                let mut tri: Vec<Vec<f64>> = Vec::new();
-                for i in 0..3 {
-                    // for each tri
-                    let idx: usize = iter.next().unwrap().parse()?;
-                    let mut cond = false;
-                    tri.push(points.get((idx - 1) % points.len()).unwrap().to_vec());
-                }
-                tris.push(tri);
-            }
-            _ => {}
+// Wavefront OBJ meshes. Only the geometry is read: v, vt, vn and f statements, where faces can
+// mix v, v/vt, v//vn and v/vt/vn corners, index from 1 or count back from the last vertex with
+// negative indices, and have any number of corners (they're split into a fan). Everything else,
+// materials and groups included, is skipped.
+
+// Every error carries the file it came from, left empty by read_obj and filled in by load_obj
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, std::io::Error),
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    IndexOutOfRange {
+        path: PathBuf,
+        line: usize,
+        attribute: &'static str,
+        index: i64,
+        count: usize,
+    },
+}
+impl ObjError {
+    pub fn path(&self) -> &Path {
+        match self {
+            ObjError::Io(path, _) => path,
+            ObjError::Parse { path, .. } => path,
+            ObjError::IndexOutOfRange { path, .. } => path,
+        }
+    }
+    fn with_path(mut self, file: &Path) -> ObjError {
+        match &mut self {
+            ObjError::Io(path, _) => *path = file.to_path_buf(),
+            ObjError::Parse { path, .. } => *path = file.to_path_buf(),
+            ObjError::IndexOutOfRange { path, .. } => *path = file.to_path_buf(),
+        }
+        self
+    }
+}
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // "file:line: message", without the file for errors from read_obj
+        let file = self.path().display().to_string();
+        let at = if file.is_empty() { String::new() } else { format!("{}:", file) };
+        match self {
+            ObjError::Io(_, e) if file.is_empty() => write!(f, "read error: {}", e),
+            ObjError::Io(_, e) => write!(f, "couldn't read {}: {}", file, e),
+            ObjError::Parse { line, message, .. } => write!(f, "{}{}: {}", at, line, message),
+            ObjError::IndexOutOfRange {
+                line,
+                attribute,
+                index,
+                count,
+                ..
+            } => write!(
+                f,
+                "{}{}: {} index {} is out of range, there are {}",
+                at, line, attribute, index, count
+            ),
         }
     }
-    return Ok(tris);
 }
+impl std::error::Error for ObjError {}
 
 pub struct TriData {
     pub v1:Vector3<f64>,
@@ -61,112 +81,360 @@ pub struct TriData {
     pub vn1:Vector3<f64>,
     pub vn2:Vector3<f64>,
 }
-//TODO -- struct for imported faces. Return vec of that. Make fn to create a mesh from them. include normals.
-pub fn objToTrilist() -> Result<Vec<TriData>, Box<dyn std::error::Error>> {
-    let path = Path::new("./src/blendermonkey_attributes.obj"); // TODO, make an argument
-    let mut i = 0;
-    let mut points = Vec::new(); //make this work
-    let mut UVs = Vec::new(); //make this work
-    let mut normals = Vec::new(); //make this work
-    let mut tris: Vec<TriData> = Vec::new(); //<((f64, f64, f64), (f64, f64, f64), (f64, f64, f64))>; //make this work
-                                             // make lineed reader here //....
-    let f = File::open(path)?;
-    let reader = BufReader::new(&f);
-    let mut i = 0;
-    let mut hasUVs = false;
-    let mut hasNormals = false;
-    for line in reader.lines() {
-        let text = &line?[..];
-        i += 1;
-        match &text.split_whitespace().next() {
-            Some("v") => {
-                let mut iter = text.trim().split_whitespace();
-                assert_eq!(Some("v"), iter.next()); // Consume prefix
-                let mut position = vec![0.0, 0.0, 0.0];
-                position[0] = iter.next().unwrap().parse()?;
-                position[1] = iter.next().unwrap().parse()?;
-                position[2] = iter.next().unwrap().parse()?;
-                points.push(position);
+impl TriData {
+    // Files without vn lines leave the normals zeroed
+    pub fn has_normals(&self) -> bool {
+        let zero = Vector3::zero();
+        self.vn0 != zero || self.vn1 != zero || self.vn2 != zero
+    }
+}
+
+pub fn load_obj(path: &Path) -> Result<Vec<TriData>, ObjError> {
+    let f = File::open(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+    read_obj(BufReader::new(f)).map_err(|e| e.with_path(path))
+}
+
+// One face corner's indices as written, and how many of each attribute came before the face so
+// negative ones can be resolved once the whole file is in
+struct Corner {
+    v: i64,
+    vt: Option<i64>,
+    vn: Option<i64>,
+}
+struct Face {
+    line: usize,
+    corners: Vec<Corner>,
+    counts: [usize; 3],
+}
+
+// The same as load_obj for anything else that reads like a file. Errors come without a path
+pub fn read_obj<R: BufRead>(reader: R) -> Result<Vec<TriData>, ObjError> {
+    let (mut points, mut uvs, mut normals, mut faces) = (vec![], vec![], vec![], vec![]);
+    for (i, line) in reader.lines().enumerate() {
+        let line_no = i + 1;
+        let text = line.map_err(|e| ObjError::Io(PathBuf::new(), e))?;
+        let mut tokens = text.split('#').next().unwrap().split_whitespace();
+        match tokens.next() {
+            // positions can carry a w or a vertex color after them, neither of which is used
+            Some("v") => points.push(numbers(tokens, 3, line_no, "v")?),
+            // v and w are optional for a uv
+            Some("vt") => uvs.push(numbers(tokens, 1, line_no, "vt")?),
+            Some("vn") => normals.push(numbers(tokens, 3, line_no, "vn")?),
+            Some("f") => {
+                let corners = tokens.map(|c| corner(c, line_no)).collect::<Result<Vec<Corner>, ObjError>>()?;
+                if corners.len() < 3 {
+                    return Err(ObjError::Parse {
+                        path: PathBuf::new(),
+                        line: line_no,
+                        message: format!("a face needs at least 3 corners, found {}", corners.len()),
+                    });
+                }
+                faces.push(Face {
+                    line: line_no,
+                    corners: corners,
+                    counts: [points.len(), uvs.len(), normals.len()],
+                });
             }
-            Some("vn") => {
-                if !hasNormals {
-                    hasNormals = true
-                };
-                let mut iter = text.trim().split_whitespace();
-                assert_eq!(Some("vn"), iter.next()); // Consume prefix
-                let mut position = vec![0.0, 0.0, 0.0];
-                position[0] = iter.next().unwrap().parse()?;
-                position[1] = iter.next().unwrap().parse()?;
-                position[2] = iter.next().unwrap().parse()?;
-                normals.push(position);
+            _ => {}
+        }
+    }
+    let mut tris = Vec::with_capacity(faces.len());
+    for face in faces.iter() {
+        let resolve = |index: i64, before: usize, all: &Vec<Vector3<f64>>, attribute: &'static str| {
+            // from 1 at the start of the file, or from -1 at the last one before the face
+            let (i, count) = if index < 0 {
+                (before as i64 + index, before)
+            } else {
+                (index - 1, all.len())
+            };
+            if index == 0 || i < 0 || i >= count as i64 {
+                return Err(ObjError::IndexOutOfRange {
+                    path: PathBuf::new(),
+                    line: face.line,
+                    attribute: attribute,
+                    index: index,
+                    count: count,
+                });
             }
-            Some("vt") => {
-                if !hasUVs {
-                    hasUVs = true
-                };
-                let mut iter = text.trim().split_whitespace();
-                assert_eq!(Some("vt"), iter.next()); // Consume prefix
-                let mut position = vec![0.0, 0.0, 0.0];
-                position[0] = iter.next().unwrap().parse()?;
-                position[1] = iter.next().unwrap().parse()?;
-                UVs.push(position);
+            Ok(all[i as usize])
+        };
+        let mut corners = Vec::with_capacity(face.corners.len());
+        for c in face.corners.iter() {
+            let p = resolve(c.v, face.counts[0], &points, "vertex")?;
+            let uv = match c.vt {
+                Some(vt) => Some(resolve(vt, face.counts[1], &uvs, "uv")?),
+                None => None,
+            };
+            let n = match c.vn {
+                Some(vn) => Some(resolve(vn, face.counts[2], &normals, "normal")?),
+                None => None,
+            };
+            corners.push((p, uv, n));
+        }
+        // a fan around the first corner. Normals and uvs are only kept when all three corners have them
+        for k in 1..corners.len() - 1 {
+            let (a, b, c) = (corners[0], corners[k], corners[k + 1]);
+            let (uv, n) = match ((a.1, b.1, c.1), (a.2, b.2, c.2)) {
+                ((Some(t0), Some(t1), Some(t2)), (Some(n0), Some(n1), Some(n2))) => ([t0, t1, t2], [n0, n1, n2]),
+                ((Some(t0), Some(t1), Some(t2)), _) => ([t0, t1, t2], [Vector3::zero(); 3]),
+                (_, (Some(n0), Some(n1), Some(n2))) => ([Vector3::zero(); 3], [n0, n1, n2]),
+                _ => ([Vector3::zero(); 3], [Vector3::zero(); 3]),
+            };
+            tris.push(TriData {
+                v0: a.0,
+                v1: b.0,
+                v2: c.0,
+                vt0: uv[0],
+                vt1: uv[1],
+                vt2: uv[2],
+                vn0: n[0],
+                vn1: n[1],
+                vn2: n[2],
+            });
+        }
+    }
+    Ok(tris)
+}
+
+// Up to three numbers, at least `needed` of them, with the rest 0
+fn numbers<'a, I: Iterator<Item = &'a str>>(
+    tokens: I,
+    needed: usize,
+    line: usize,
+    statement: &str,
+) -> Result<Vector3<f64>, ObjError> {
+    let mut v = [0.0; 3];
+    let mut found = 0;
+    for (i, t) in tokens.take(3).enumerate() {
+        v[i] = match t.parse::<f64>() {
+            Ok(n) if n.is_finite() => n,
+            _ => {
+                return Err(ObjError::Parse {
+                    path: PathBuf::new(),
+                    line: line,
+                    message: format!("'{}' is not a number", t),
+                })
             }
-            _ => {}
+        };
+        found += 1;
+    }
+    if found < needed {
+        return Err(ObjError::Parse {
+            path: PathBuf::new(),
+            line: line,
+            message: format!("'{}' needs {} numbers, found {}", statement, needed, found),
+        });
+    }
+    Ok(Vector3::from_tuple((v[0], v[1], v[2])))
+}
+
+// v, v/vt, v//vn or v/vt/vn
+fn corner(text: &str, line: usize) -> Result<Corner, ObjError> {
+    let bad = || ObjError::Parse {
+        path: PathBuf::new(),
+        line: line,
+        message: format!("'{}' is not a face corner, expected v, v/vt, v//vn or v/vt/vn", text),
+    };
+    let parts: Vec<&str> = text.split('/').collect();
+    if parts.len() > 3 {
+        return Err(bad());
+    }
+    let index = |i: usize| -> Result<Option<i64>, ObjError> {
+        match parts.get(i) {
+            None => Ok(None),
+            Some(p) if p.is_empty() && i > 0 => Ok(None),
+            Some(p) => p.parse::<i64>().map(Some).map_err(|_| bad()),
+        }
+    };
+    Ok(Corner {
+        v: index(0)?.unwrap(),
+        vt: index(1)?,
+        vn: index(2)?,
+    })
+}
+
+// Unit normal of the triangle wound p0, p1, p2, zero if it's degenerate
+pub fn face_normal(p0: &Vector3<f64>, p1: &Vector3<f64>, p2: &Vector3<f64>) -> Vector3<f64> {
+    (*p1 - *p0).cross(&(*p2 - *p0)).normalized()
+}
+
+// Gives the triangles that came without vertex normals smooth ones. A corner's normal is the sum of
+// the face normals around its position, each weighted by the face's angle at that corner, so how
+// the faces happen to be split up doesn't matter. Faces only blend when they meet at less than
+// crease_degrees, which keeps hard edges hard. Triangles that already have normals are left alone
+// and don't contribute
+pub fn generate_normals(tris: &mut [TriData], crease_degrees: f64) {
+    let key = |p: &Vector3<f64>| (p.x.to_bits(), p.y.to_bits(), p.z.to_bits());
+    let faces: Vec<Vector3<f64>> = tris.iter().map(|t| face_normal(&t.v0, &t.v1, &t.v2)).collect();
+    let mut around: HashMap<(u64, u64, u64), Vec<(usize, f64)>> = HashMap::new();
+    for (i, t) in tris.iter().enumerate() {
+        if t.has_normals() {
+            continue;
+        }
+        let corners = [t.v0, t.v1, t.v2];
+        for c in 0..3 {
+            let p = corners[c];
+            let a = (corners[(c + 1) % 3] - p).normalized();
+            let b = (corners[(c + 2) % 3] - p).normalized();
+            let angle = a.dot(&b).max(-1.0).min(1.0).acos();
+            around.entry(key(&p)).or_insert_with(Vec::new).push((i, angle));
         }
     }
-    let mut reader = BufReader::new(&f);
-    reader.seek(std::io::SeekFrom::Start(0));
-    let mut i = 0;
-    for line in reader.lines() {
-        let text = &line?[..];
-        match &text[0..2] {
-            "f " => {
-                let mut triP: Vec<Vec<f64>> = Vec::new();
-                let mut triN: Vec<Vec<f64>> = Vec::new();
-                let mut triVT: Vec<Vec<f64>> = Vec::new();
-                let mut faceIter = text.trim().split_whitespace();
-                assert_eq!(Some("f"), faceIter.next()); // Consume prefix
-                for i in 0..3 {
-                    // for each vert
-                    match hasUVs || hasNormals {
-                        //Incomplete, there are probably more params that can cause /s
-                        true => {
-                            let mut componentIter = faceIter.next().unwrap().trim().split("/");
-                            let idx: usize = componentIter.next().unwrap().parse()?;
-                            triP.push(points.get((idx - 1) % points.len()).unwrap().to_vec());
-                            
-                            let idx: usize = componentIter.next().unwrap().parse()?;
-                            if hasUVs {
-                                triVT.push(UVs.get((idx - 1) % UVs.len()).unwrap().to_vec());
-                            }
-                            if hasNormals {
-                                let idx: usize = componentIter.next().unwrap().parse()?;
-                                triN.push(normals.get((idx - 1) % normals.len()).unwrap().to_vec());
-                            }
-                        }
-                        false => {
-                            let idx: usize = faceIter.next().unwrap().parse()?;
-                            triP.push(points.get((idx - 1) % points.len()).unwrap().to_vec());
-                        }
-                    }
+    let cos_crease = crease_degrees.to_radians().cos();
+    for (i, t) in tris.iter_mut().enumerate() {
+        if t.has_normals() {
+            continue;
+        }
+        let mut normals = [Vector3::zero(); 3];
+        for (c, p) in [t.v0, t.v1, t.v2].iter().enumerate() {
+            let mut n = Vector3::zero();
+            for &(j, angle) in around[&key(p)].iter() {
+                if faces[j].dot(&faces[i]) >= cos_crease {
+                    n = n + angle * faces[j];
                 }
-                #[rustfmt::skip] 
-                tris.push(TriData {
-                    v0: Vector3::from_vector(triP[0].to_vec()),
-                    v1: Vector3::from_vector(triP[1].to_vec()),
-                    v2: Vector3::from_vector(triP[2].to_vec()),
-                    vt0: if hasUVs {Vector3::from_vector(triVT[0].to_vec())} else {Vector3::zero()},
-                    vt1: if hasUVs {Vector3::from_vector(triVT[1].to_vec())} else {Vector3::zero()},
-                    vt2: if hasUVs {Vector3::from_vector(triVT[2].to_vec())} else {Vector3::zero()},
-                    vn0: if hasNormals {Vector3::from_vector(triN[0].to_vec())} else {Vector3::zero()},
-                    vn1: if hasNormals {Vector3::from_vector(triN[1].to_vec())} else {Vector3::zero()},
-                    vn2: if hasNormals { Vector3::from_vector(triN[2].to_vec())} else {Vector3::zero()},
-                });
             }
-            _ => {}
+            normals[c] = if n.sqrmagnitude() > 0.0 { n.normalized() } else { faces[i] };
+        }
+        t.vn0 = normals[0];
+        t.vn1 = normals[1];
+        t.vn2 = normals[2];
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tri(p0: (f64, f64, f64), p1: (f64, f64, f64), p2: (f64, f64, f64)) -> TriData {
+        TriData {
+            v0: Vector3::from_tuple(p0),
+            v1: Vector3::from_tuple(p1),
+            v2: Vector3::from_tuple(p2),
+            vt0: Vector3::zero(),
+            vt1: Vector3::zero(),
+            vt2: Vector3::zero(),
+            vn0: Vector3::zero(),
+            vn1: Vector3::zero(),
+            vn2: Vector3::zero(),
+        }
+    }
+
+    fn obj_err(text: &str) -> ObjError {
+        match read_obj(text.as_bytes()) {
+            Err(e) => e,
+            Ok(tris) => panic!("expected an error, got {} triangles", tris.len()),
         }
-        i += 1;
-        
     }
-    return Ok(tris);
+
+    #[test]
+    fn reads_corner_formats_negative_indices_and_polygons() {
+        let tris = read_obj(
+            "# a unit square and a triangle\n\
+             o square\n\
+             v 0 0 0\n\
+             v 1 0 0 1.0\n\
+             v 1 1 0\n\
+             v 0 1 0\n\
+             vt 0 0\n\
+             vt 1\n\
+             vn 0 0 1\n\
+             usemtl whatever\n\
+             f 1/1/1 2/2/1 3//1 4/1/1\n\
+             f -4//-1 -3//-1 -1//1   # trailing comment\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(tris.len(), 3);
+        // the quad is fanned around its first corner
+        assert_eq!(tris[1].v0, Vector3::zero());
+        assert_eq!(tris[1].v2, Vector3::from_tuple((0.0, 1.0, 0.0)));
+        // one corner without a uv leaves the triangle without any, normals are still there
+        assert_eq!(tris[0].vt1, Vector3::zero());
+        assert!(tris[0].has_normals());
+        // vt with just a u
+        assert_eq!(tris[1].vt0, Vector3::zero());
+        assert_eq!(tris[2].v1, Vector3::from_tuple((1.0, 0.0, 0.0)));
+        assert_eq!(tris[2].v2, Vector3::from_tuple((0.0, 1.0, 0.0)));
+        assert_eq!(tris[2].vn2, Vector3::from_tuple((0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn reports_bad_files_with_their_line() {
+        match obj_err("v 0 0 0\nv 1 0 zero\n") {
+            ObjError::Parse { line, .. } => assert_eq!(line, 2),
+            e => panic!("{}", e),
+        }
+        match obj_err("v 0 0\n") {
+            ObjError::Parse { line, message, .. } => assert_eq!((line, message.as_str()), (1, "'v' needs 3 numbers, found 2")),
+            e => panic!("{}", e),
+        }
+        match obj_err("v 0 0 0\nv 1 0 0\nf 1 2\n") {
+            ObjError::Parse { line, .. } => assert_eq!(line, 3),
+            e => panic!("{}", e),
+        }
+        match obj_err("v 0 0 0\nf 1 2/x 1\n") {
+            ObjError::Parse { line, .. } => assert_eq!(line, 2),
+            e => panic!("{}", e),
+        }
+        for &(text, attribute, index) in [
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 1 2 4\n", "vertex", 4),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 0 1 2\n", "vertex", 0),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 1 2 -4\n", "vertex", -4),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 1//1 2//1 3//1\n", "normal", 1),
+        ]
+        .iter()
+        {
+            match obj_err(text) {
+                ObjError::IndexOutOfRange { line, attribute: a, index: i, count, .. } => {
+                    assert_eq!((line, a, i), (5, attribute, index));
+                    assert_eq!(count, if attribute == "vertex" { 3 } else { 0 });
+                }
+                e => panic!("{}", e),
+            }
+        }
+        match load_obj(Path::new("no/such/mesh.obj")) {
+            Err(e @ ObjError::Io(..)) => assert!(e.to_string().starts_with("couldn't read no/such/mesh.obj: ")),
+            other => panic!("{:?}", other.map(|t| t.len())),
+        }
+        assert_eq!(obj_err("v 0 0\n").to_string(), "1: 'v' needs 3 numbers, found 2");
+        // errors in a file name it
+        let file = std::env::temp_dir().join(format!("bad_mesh_{}.obj", std::process::id()));
+        std::fs::write(&file, "v 0 0 0\nf 1 2 3\n").unwrap();
+        let e = load_obj(&file).err().unwrap();
+        std::fs::remove_file(&file).ok();
+        assert_eq!(e.path(), file.as_path());
+        assert_eq!(e.to_string(), format!("{}:2: vertex index 2 is out of range, there are 1", file.display()));
+    }
+
+    // Two faces folded 90 degrees along the z axis, one facing +y and one facing +x
+    #[test]
+    fn smooth_normals_respect_the_crease_angle() {
+        let fold = || {
+            vec![
+                tri((0.0, 0.0, 0.0), (0.0, 0.0, 1.0), (1.0, 0.0, 0.0)),
+                tri((0.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, 0.0, 1.0)),
+            ]
+        };
+        let up = Vector3::from_tuple((0.0, 1.0, 0.0));
+        assert_eq!(face_normal(&fold()[0].v0, &fold()[0].v1, &fold()[0].v2), up);
+
+        let mut hard = fold();
+        generate_normals(&mut hard, 60.0);
+        assert_eq!((hard[0].vn0, hard[0].vn1, hard[0].vn2), (up, up, up));
+
+        let mut soft = fold();
+        generate_normals(&mut soft, 120.0);
+        let diagonal = Vector3::from_tuple((1.0, 1.0, 0.0)).normalized();
+        assert!((soft[0].vn0 - diagonal).magnitude() < 1e-9, "{}", soft[0].vn0);
+        assert!((soft[1].vn2 - diagonal).magnitude() < 1e-9, "{}", soft[1].vn2);
+        // the corner off the fold only touches its own face
+        assert_eq!(soft[0].vn2, up);
+
+        // normals that came with the mesh are kept
+        let mut given = fold();
+        given[0].vn0 = Vector3::from_tuple((0.0, 0.0, 1.0));
+        generate_normals(&mut given, 120.0);
+        assert_eq!(given[0].vn0, Vector3::from_tuple((0.0, 0.0, 1.0)));
+        assert_eq!(given[0].vn1, Vector3::zero());
+        assert_eq!(given[1].vn0, Vector3::from_tuple((1.0, 0.0, 0.0)));
+    }
 }
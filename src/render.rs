@@ -0,0 +1,1032 @@
+use crate::aov::{AovPixel, AovSample};
+use crate::background::{Background, GradientBackground};
+use crate::camera::Camera;
+use crate::filter::Filter;
+use crate::hit::*;
+use crate::light::{light_radiance, LightList};
+use crate::ray::Ray;
+use crate::imageio::LdrImage;
+use crate::rng::hash64;
+use crate::sampler::{Sampler, SamplerKind};
+use crate::tiles::{make_tiles, Tile, TileOrder};
+use crate::tonemap::luminance;
+use crate::tonemap::ToneMapping;
+use crate::vectors::Vector3 as Color;
+use crate::world::World;
+use crate::BVH::bvhNode;
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+pub static TRI_COUNT: AtomicUsize = AtomicUsize::new(0);
+pub static RAY_COUNT: AtomicUsize = AtomicUsize::new(0);
+pub static PATH_COUNT: AtomicUsize = AtomicUsize::new(0);
+pub static PATH_SEGMENTS: AtomicUsize = AtomicUsize::new(0);
+
+// Rays traced per camera path so far, shadow rays not included
+pub fn average_path_length() -> f64 {
+    let paths = PATH_COUNT.load(Ordering::Acquire);
+    if paths == 0 {
+        return 0.0;
+    }
+    PATH_SEGMENTS.load(Ordering::Acquire) as f64 / paths as f64
+}
+
+pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
+    if x < min {
+        return min;
+    };
+    if x > max {
+        return max;
+    };
+    return x;
+}
+
+pub fn from_u8_rgb(r: u8, g: u8, b: u8) -> u32 {
+    let (r, g, b) = (r as u32, g as u32, b as u32);
+    (r << 16) | (g << 8) | b
+}
+
+// The sRGB OETF, linear light to display code values
+pub fn linear_to_srgb(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn to_srgb8(x: f64) -> u8 {
+    (255.0 * clamp(linear_to_srgb(x), 0.0, 1.0)).round() as u8
+}
+
+// sRGB + clamp, packed for minifb
+pub fn to_display(c: Color<f64>) -> u32 {
+    from_u8_rgb(to_srgb8(c.x), to_srgb8(c.y), to_srgb8(c.z))
+}
+
+// Triangular noise in (-1, 1) code values, hashed from the pixel so dithered output is repeatable
+fn dither_noise(pixel: usize, channel: usize) -> f64 {
+    let h = hash64((pixel * 3 + channel) as u64);
+    let a = (h >> 40) as f64 / (1u64 << 24) as f64;
+    let b = ((h >> 16) & 0xff_ffff) as f64 / (1u64 << 24) as f64;
+    a - b
+}
+
+pub fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other * other);
+    if a + b <= 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+// One light sample for the hit point, MIS weighted against the BSDF having picked the same direction
+fn sample_direct(r: &Ray, hit: &HitInfo, scene: &Scene, sampler: &mut dyn Sampler) -> Color<f64> {
+    let sample = match scene.lights.sample(&hit.p, sampler) {
+        Some(s) => s,
+        None => return Color::zero(),
+    };
+    let f = hit.mat.eval(r, hit, &sample.dir);
+    if f.x <= 0.0 && f.y <= 0.0 && f.z <= 0.0 {
+        return Color::zero();
+    }
+    let le = light_radiance(&sample, &hit.p, &scene.world);
+    let weight = power_heuristic(sample.pdf, hit.mat.pdf(r, hit, &sample.dir));
+    &(f * &le) * (weight / sample.pdf)
+}
+
+// Follows one camera path for up to max_depth rays. After rr_depth bounces the path survives with
+// a probability matching its remaining throughput, and survivors are scaled up to stay unbiased.
+// aov gets what the first ray hit
+pub fn raycolor(
+    r: &Ray,
+    scene: &Scene,
+    max_depth: u32,
+    rr_depth: u32,
+    sampler: &mut dyn Sampler,
+    mut aov: Option<&mut AovSample>,
+) -> Color<f64> {
+    let mut color: Color<f64> = Color::zero();
+    let mut throughput: Color<f64> = Color::one();
+    let mut ray = Ray::new(r.origin, r.dir);
+    // density the last bounce picked ray with, None for camera rays and delta lobes whose
+    // emission hits can't also come from light sampling
+    let mut bsdf_pdf: Option<f64> = None;
+    let mut segments = 0;
+    while segments < max_depth {
+        segments += 1;
+        RAY_COUNT.fetch_add(1, Ordering::Relaxed);
+        let hit = match scene.world.hit(&ray, 0.001, f64::INFINITY) {
+            Some(hit) => hit,
+            None => {
+                let background = scene.background.color(&ray);
+                if let Some(aov) = aov.take() {
+                    *aov = AovSample::miss(background);
+                }
+                color = color + throughput * &background;
+                break;
+            }
+        };
+        if let Some(aov) = aov.take() {
+            *aov = AovSample {
+                depth: hit.t * ray.dir.magnitude(),
+                normal: hit.normal,
+                position: hit.p,
+                albedo: hit.mat.albedo(&hit),
+                ids: Some((hit.object_id, scene.object_materials[hit.object_id as usize])),
+            };
+        }
+        let mut emitted = hit.mat.emitted(&ray, &hit);
+        if let Some(pdf) = bsdf_pdf {
+            if hit.mat.is_light() {
+                let light_pdf = scene.lights.pdf(hit.object_id, &ray.origin, &hit.p, &hit.normal);
+                emitted = &emitted * power_heuristic(pdf, light_pdf);
+            }
+        }
+        color = color + throughput * &(emitted + sample_direct(&ray, &hit, scene, sampler));
+        let result = match hit.mat.scatter(&ray, &hit, sampler) {
+            Some(result) => result,
+            None => break,
+        };
+        throughput = throughput * &result.attenuation;
+        bsdf_pdf = if result.specular { None } else { Some(result.pdf) };
+        if segments >= rr_depth {
+            let survive = throughput.x.abs().max(throughput.y.abs()).max(throughput.z.abs()).min(1.0);
+            if sampler.get_1d() >= survive {
+                break;
+            }
+            throughput = &throughput * (1.0 / survive);
+        }
+        ray = result.ray;
+    }
+    PATH_COUNT.fetch_add(1, Ordering::Relaxed);
+    PATH_SEGMENTS.fetch_add(segments as usize, Ordering::Relaxed);
+    color
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples: u32,
+    pub max_depth: u32,
+    pub rr_depth: u32,
+    pub seed: u64,
+}
+// Bounds on the settings, shared by the command line and scene files. Image sides also have to
+// fit the 16 bit size fields of some output formats
+pub const MAX_IMAGE_SIDE: u32 = 16384;
+pub const MAX_SAMPLES: u32 = 1 << 20;
+pub const MAX_PATH_DEPTH: u32 = 10000;
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 1200,
+            height: 800,
+            samples: 500,
+            max_depth: 50,
+            rr_depth: 3,
+            seed: 0,
+        }
+    }
+}
+impl RenderSettings {
+    pub fn aspect_ratio(&self) -> f64 {
+        self.width as f64 / self.height as f64
+    }
+}
+
+// Everything the integrator needs to trace: the world behind a BVH, its lights, a camera and what's behind it all
+pub struct Scene {
+    pub world: bvhNode,
+    pub camera: Camera,
+    pub background: Arc<dyn Background + Send + Sync>,
+    pub lights: LightList,
+    pub object_materials: Vec<u32>, // material id of each object id, for the ID AOVs
+}
+impl Scene {
+    pub fn new(world: World, camera: Camera) -> Scene {
+        Scene {
+            world: bvhNode::create_from_hlist(Arc::new(world.objects)).unwrap(),
+            camera: camera,
+            background: Arc::new(GradientBackground::default()),
+            lights: world.lights,
+            object_materials: world.object_materials,
+        }
+    }
+}
+
+// Linear RGB, row 0 at the top of the image
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color<f64>>,
+}
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width: width,
+            height: height,
+            pixels: vec![Color::zero(); width * height],
+        }
+    }
+    pub fn get(&self, x: usize, y: usize) -> Color<f64> {
+        self.pixels[y * self.width + x]
+    }
+    pub fn to_display(&self) -> Vec<u32> {
+        self.pixels.iter().map(|c| to_display(*c)).collect()
+    }
+    // sRGB encoded 8 or 16 bit image for the LDR writers
+    pub fn to_ldr(&self, bit_depth: u8, dither: bool) -> LdrImage {
+        let max = ((1u32 << bit_depth) - 1) as f64;
+        let pixels = self
+            .pixels
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let mut out = [0u16; 3];
+                for (ch, v) in [c.x, c.y, c.z].iter().enumerate() {
+                    let noise = if dither { dither_noise(i, ch) } else { 0.0 };
+                    let q = clamp(linear_to_srgb(*v), 0.0, 1.0) * max + noise;
+                    out[ch] = clamp(q.round(), 0.0, max) as u16;
+                }
+                out
+            })
+            .collect();
+        LdrImage {
+            width: self.width,
+            height: self.height,
+            bit_depth: bit_depth,
+            pixels: pixels,
+        }
+    }
+    // Tone mapped copy, still linear. Only the LDR outputs and the preview go through this
+    pub fn tonemapped(&self, tm: &ToneMapping) -> Framebuffer {
+        let gains = tm.white_balance_gains();
+        Framebuffer {
+            width: self.width,
+            height: self.height,
+            pixels: self
+                .pixels
+                .iter()
+                .map(|c| tm.apply_with_gains(*c, &gains))
+                .collect(),
+        }
+    }
+    pub fn to_rgb32(&self) -> Vec<[f32; 3]> {
+        self.pixels
+            .iter()
+            .map(|c| [c.x as f32, c.y as f32, c.z as f32])
+            .collect()
+    }
+}
+
+// What a run of samples added up to for one pixel. The luminance squares are there for the
+// variance estimate adaptive sampling stops on
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PixelSamples {
+    pub sum: Color<f64>,
+    pub lum_sq: f64,
+    pub count: u32,
+}
+impl PixelSamples {
+    pub fn new() -> PixelSamples {
+        PixelSamples {
+            sum: Color::zero(),
+            lum_sq: 0.0,
+            count: 0,
+        }
+    }
+    pub fn add(&mut self, color: Color<f64>) {
+        let l = luminance(&color);
+        self.sum = self.sum + color;
+        self.lum_sq += l * l;
+        self.count += 1;
+    }
+    pub fn merge(&self, o: &PixelSamples) -> PixelSamples {
+        PixelSamples {
+            sum: self.sum + o.sum,
+            lum_sq: self.lum_sq + o.lum_sq,
+            count: self.count + o.count,
+        }
+    }
+    // Standard error of the mean luminance relative to the mean itself
+    pub fn relative_error(&self) -> f64 {
+        if self.count < 2 {
+            return std::f64::INFINITY;
+        }
+        let n = self.count as f64;
+        let mean = luminance(&self.sum) / n;
+        let variance = ((self.lum_sq / n - mean * mean) * n / (n - 1.0)).max(0.0);
+        (variance / n).sqrt() / mean.max(1e-3)
+    }
+}
+
+// Stop sampling a pixel once its relative error drops under threshold. Only checked every
+// ADAPTIVE_BATCH samples past min_samples, a check per sample stops too eagerly on lucky streaks
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveSampling {
+    pub threshold: f64,
+    pub min_samples: u32,
+}
+pub const ADAPTIVE_BATCH: u32 = 8;
+impl AdaptiveSampling {
+    pub fn converged(&self, s: &PixelSamples) -> bool {
+        s.count >= self.min_samples
+            && s.count % ADAPTIVE_BATCH == 0
+            && s.relative_error() < self.threshold
+    }
+}
+
+// Blue at 0 through green to red at 1
+pub fn heat_color(t: f64) -> Color<f64> {
+    let t = clamp(t, 0.0, 1.0) * 2.0;
+    if t < 1.0 {
+        Color::from_tuple((0.0, t, 1.0 - t))
+    } else {
+        Color::from_tuple((t - 1.0, 2.0 - t, 0.0))
+    }
+}
+
+// Running radiance sums and sample weights per pixel. Resolving divides one by the other. aovs
+// stays empty unless the tiles added bring some
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    pub sum: Vec<Color<f64>>,
+    pub weight: Vec<f64>,
+    pub samples: Vec<u32>,
+    pub aovs: Vec<AovPixel>,
+}
+impl Film {
+    pub fn new(width: usize, height: usize) -> Film {
+        Film {
+            width: width,
+            height: height,
+            sum: vec![Color::zero(); width * height],
+            weight: vec![0.0; width * height],
+            samples: vec![0; width * height],
+            aovs: vec![],
+        }
+    }
+    pub fn add_sample(&mut self, x: usize, y: usize, color: Color<f64>, weight: f64) {
+        let i = y * self.width + x;
+        self.sum[i] = self.sum[i] + color;
+        self.weight[i] += weight;
+        self.samples[i] += 1;
+    }
+    // A tile as handed out by Renderer::render_tile, splats spilling into its neighbours included
+    pub fn add_tile(&mut self, result: &TileResult) {
+        for i in 0..result.splat.pixel_count() {
+            let p = result.splat.pixel_index(i, self.width);
+            self.sum[p] = self.sum[p] + result.sum[i];
+            self.weight[p] += result.weight[i];
+        }
+        for (i, s) in result.pixels.iter().enumerate() {
+            self.samples[result.tile.pixel_index(i, self.width)] += s.count;
+        }
+        if !result.aovs.is_empty() && self.aovs.is_empty() {
+            self.aovs = vec![AovPixel::new(); self.width * self.height];
+        }
+        for (i, a) in result.aovs.iter().enumerate() {
+            let p = result.tile.pixel_index(i, self.width);
+            self.aovs[p] = self.aovs[p].merge(a);
+        }
+    }
+    pub fn resolve_pixel(&self, i: usize) -> Color<f64> {
+        if self.weight[i] > 0.0 {
+            &self.sum[i] * (1.0 / self.weight[i])
+        } else {
+            Color::zero()
+        }
+    }
+    pub fn resolve(&self) -> Framebuffer {
+        let mut fb = Framebuffer::new(self.width, self.height);
+        for (i, p) in fb.pixels.iter_mut().enumerate() {
+            *p = self.resolve_pixel(i);
+        }
+        fb
+    }
+    pub fn average_samples(&self) -> f64 {
+        self.samples.iter().map(|s| *s as f64).sum::<f64>() / self.samples.len() as f64
+    }
+    // Samples taken per pixel, to see where adaptive sampling spent its time
+    pub fn sample_heatmap(&self, max: u32) -> Framebuffer {
+        let mut fb = Framebuffer::new(self.width, self.height);
+        for (p, s) in fb.pixels.iter_mut().zip(self.samples.iter()) {
+            *p = heat_color(*s as f64 / max.max(1) as f64);
+        }
+        fb
+    }
+}
+
+// One rendered tile: what was sampled in each of its pixels, and the filtered sample splats over
+// the tile grown by however far the filter reaches. aovs has one entry per tile pixel when the
+// renderer records them
+#[derive(Clone)]
+pub struct TileResult {
+    pub tile: Tile,
+    pub pixels: Vec<PixelSamples>,
+    pub splat: Tile,
+    pub sum: Vec<Color<f64>>,
+    pub weight: Vec<f64>,
+    pub aovs: Vec<AovPixel>,
+}
+impl Default for TileResult {
+    fn default() -> TileResult {
+        TileResult::new()
+    }
+}
+impl TileResult {
+    pub fn new() -> TileResult {
+        let empty = Tile {
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+        };
+        TileResult {
+            tile: empty,
+            pixels: Vec::new(),
+            splat: empty,
+            sum: Vec::new(),
+            weight: Vec::new(),
+            aovs: Vec::new(),
+        }
+    }
+    fn reset(&mut self, tile: &Tile, reach: usize, width: usize, height: usize) {
+        let (x0, y0) = (tile.x.saturating_sub(reach), tile.y.saturating_sub(reach));
+        let x1 = (tile.x + tile.width + reach).min(width);
+        let y1 = (tile.y + tile.height + reach).min(height);
+        self.tile = *tile;
+        self.splat = Tile {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+        };
+        self.pixels.clear();
+        self.aovs.clear();
+        self.sum.clear();
+        self.sum.resize(self.splat.pixel_count(), Color::zero());
+        self.weight.clear();
+        self.weight.resize(self.splat.pixel_count(), 0.0);
+    }
+    // Adds a sample at film position (px, py) to every pixel the filter reaches
+    fn splat(&mut self, filter: &Filter, px: f64, py: f64, color: Color<f64>) {
+        let s = self.splat;
+        let range = |p: f64, start: usize, len: usize| {
+            // pixel centers in (p - r, p + r]
+            let lo = ((p - filter.radius - 0.5).floor() + 1.0).max(start as f64) as usize;
+            let hi = (p + filter.radius - 0.5).floor().min((start + len) as f64 - 1.0);
+            if hi < lo as f64 {
+                lo..lo
+            } else {
+                lo..hi as usize + 1
+            }
+        };
+        // separable, so each axis is only evaluated once
+        let xs = range(px, s.x, s.width);
+        let wx: Vec<f64> = xs.clone().map(|x| filter.eval_1d(px - (x as f64 + 0.5))).collect();
+        for y in range(py, s.y, s.height) {
+            let wy = filter.eval_1d(py - (y as f64 + 0.5));
+            for (x, wx) in xs.clone().zip(wx.iter()) {
+                let w = wx * wy;
+                if w != 0.0 {
+                    let i = (y - s.y) * s.width + (x - s.x);
+                    self.sum[i] = self.sum[i] + &color * w;
+                    self.weight[i] += w;
+                }
+            }
+        }
+    }
+}
+
+// What the progressive renderer reports back. Started lets the preview mark tiles being worked on
+pub enum TileEvent {
+    Started(Tile),
+    Finished { pass: u32, result: TileResult },
+}
+
+#[derive(Clone)]
+pub struct Renderer {
+    pub settings: RenderSettings,
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    pub aovs: bool,
+}
+impl Renderer {
+    pub fn new(settings: RenderSettings) -> Renderer {
+        Renderer {
+            settings: settings,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            adaptive: None,
+            sampler: SamplerKind::Sobol,
+            filter: Filter::default(),
+            aovs: false,
+        }
+    }
+    pub fn tiles(&self) -> Vec<Tile> {
+        make_tiles(
+            self.settings.width,
+            self.settings.height,
+            self.tile_size,
+            self.tile_order,
+        )
+    }
+    // Sample k of pixel (x, y), x, y in framebuffer coordinates (y down), and where in the pixel it
+    // went through. The sampler's values only depend on the seed, the pixel and k, so the same k
+    // always traces the same path
+    pub fn trace_sample(
+        &self,
+        scene: &Scene,
+        x: usize,
+        y: usize,
+        k: u32,
+        sampler: &mut dyn Sampler,
+        aov: Option<&mut AovSample>,
+    ) -> (Color<f64>, (f64, f64)) {
+        let RenderSettings {
+            width,
+            height,
+            max_depth,
+            rr_depth,
+            ..
+        } = self.settings;
+        sampler.start_sample((y * width + x) as u64, k);
+        let (du, dv) = sampler.get_2d();
+        // the camera's v runs bottom to top
+        let u = (x as f64 + du) / (width) as f64;
+        let v = 1.0 - (y as f64 + dv) / (height) as f64;
+        let r = scene.camera.get_ray(u, v, sampler.get_2d());
+        (raycolor(&r, scene, max_depth, rr_depth, sampler, aov), (du, dv))
+    }
+    // Samples `samples` for every pixel of the tile into out. `before` is what earlier calls already
+    // took per pixel, so adaptive sampling can carry on where they left off
+    pub fn render_tile(
+        &self,
+        scene: &Scene,
+        tile: &Tile,
+        samples: Range<u32>,
+        before: Option<&[PixelSamples]>,
+        out: &mut TileResult,
+    ) {
+        out.reset(tile, self.filter.pixel_reach(), self.settings.width, self.settings.height);
+        let mut sampler = self.sampler.create(self.settings.seed, self.settings.samples);
+        for i in 0..tile.pixel_count() {
+            let (x, y) = (tile.x + i % tile.width, tile.y + i / tile.width);
+            let prev = before.map_or(PixelSamples::new(), |b| b[i]);
+            // added in sample order, so the result doesn't depend on how the work was split up
+            let mut s = PixelSamples::new();
+            let mut aovs = AovPixel::new();
+            let mut aov = AovSample::miss(Color::zero());
+            for k in samples.clone() {
+                if let Some(adaptive) = &self.adaptive {
+                    if adaptive.converged(&prev.merge(&s)) {
+                        break;
+                    }
+                }
+                let wants_aov = if self.aovs { Some(&mut aov) } else { None };
+                let (color, (du, dv)) = self.trace_sample(scene, x, y, k, &mut *sampler, wants_aov);
+                s.add(color);
+                out.splat(&self.filter, x as f64 + du, y as f64 + dv, color);
+                if self.aovs {
+                    aovs.add(&aov);
+                }
+            }
+            out.pixels.push(s);
+            if self.aovs {
+                out.aovs.push(aovs);
+            }
+        }
+    }
+    // Bucket scheduler: one worker per rayon thread, each pulling the next tile off a shared counter
+    // so tiles start in the requested order. A worker hands its own scratch buffers to work for
+    // every tile (with the tile's index). started returns false to stop handing out tiles
+    pub fn for_each_tile<S, W>(&self, tiles: &[Tile], started: S, work: W)
+    where
+        S: Fn(&Tile) -> bool + Sync,
+        W: Fn(usize, &Tile, &mut TileResult) + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        (0..rayon::current_num_threads())
+            .into_par_iter()
+            .for_each(|_| {
+                let mut scratch = TileResult::new();
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= tiles.len() || !started(&tiles[i]) {
+                        break;
+                    }
+                    work(i, &tiles[i], &mut scratch);
+                }
+            });
+    }
+    pub fn render(&self, scene: &Scene) -> Framebuffer {
+        self.render_film(scene, |_, _| {}).resolve()
+    }
+    pub fn render_with_progress<F>(&self, scene: &Scene, progress: F) -> Framebuffer
+    where
+        F: FnMut(usize, usize) + Send,
+    {
+        self.render_film(scene, progress).resolve()
+    }
+    // progress(tiles_done, tiles_total) is called after every tile
+    pub fn render_film<F>(&self, scene: &Scene, progress: F) -> Film
+    where
+        F: FnMut(usize, usize) + Send,
+    {
+        let tiles = self.tiles();
+        // Filters wider than a pixel make neighbouring tiles overlap, so they're added in tile order
+        // no matter which finished first. Otherwise the float sums would depend on the scheduling.
+        // Merged buffers go back on the spare list for the next tile a worker picks up
+        let state = Mutex::new((
+            Film::new(self.settings.width, self.settings.height),
+            BTreeMap::new(),
+            Vec::new(),
+            0,
+            progress,
+        ));
+        self.for_each_tile(&tiles, |_| true, |i, tile, result| {
+            self.render_tile(scene, tile, 0..self.settings.samples, None, result);
+            let mut guard = state.lock().unwrap();
+            let (film, waiting, spare, merged, progress) = &mut *guard;
+            let finished = std::mem::replace(result, spare.pop().unwrap_or_default());
+            waiting.insert(i, finished);
+            while let Some(next) = waiting.remove(merged) {
+                film.add_tile(&next);
+                spare.push(next);
+                *merged += 1;
+            }
+            progress(*merged + waiting.len(), tiles.len());
+        });
+        state.into_inner().unwrap().0
+    }
+    // One sample per pixel per pass over all the tiles, streamed out as events until every pass is
+    // done or cancel gets set. A pass finishes before the next starts, so with the box filter the
+    // samples of a pixel come out in the same order render() adds them
+    pub fn render_progressive(&self, scene: &Scene, events: &Sender<TileEvent>, cancel: &AtomicBool) {
+        let tiles = self.tiles();
+        let events = Mutex::new(events.clone());
+        // what each tile has taken so far, for adaptive sampling
+        let taken: Vec<Mutex<Vec<PixelSamples>>> = tiles
+            .iter()
+            .map(|t| Mutex::new(vec![PixelSamples::new(); t.pixel_count()]))
+            .collect();
+        for pass in 0..self.settings.samples {
+            if cancel.load(Ordering::Relaxed) {
+                return;
+            }
+            self.for_each_tile(
+                &tiles,
+                |tile| {
+                    !cancel.load(Ordering::Relaxed)
+                        && events.lock().unwrap().send(TileEvent::Started(*tile)).is_ok()
+                },
+                |i, tile, result| {
+                    let mut taken = taken[i].lock().unwrap();
+                    self.render_tile(scene, tile, pass..pass + 1, Some(&taken[..]), result);
+                    for (t, p) in taken.iter_mut().zip(result.pixels.iter()) {
+                        *t = t.merge(p);
+                    }
+                    let event = TileEvent::Finished {
+                        pass: pass,
+                        result: std::mem::take(result),
+                    };
+                    events.lock().unwrap().send(event).ok();
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::SolidBackground;
+    use crate::filter::FilterKind;
+    use crate::sceneLoader::CameraDescription;
+    use crate::world::matTypes;
+
+    // A camera ray straight onto an emissive sphere sees exactly its emission, from outside and
+    // from inside where it hits the back face
+    #[test]
+    fn rays_hitting_a_light_return_its_radiance() {
+        let mut world = World::new();
+        world.addMat("light".to_string(), matTypes::emissive, (1.0, 0.5, 0.25), 4.0, 1.0);
+        world.addSphere((0.0, 0.0, 0.0), 1.0, "light".to_string());
+        let mut scene = Scene::new(world, CameraDescription::default().build(1.0));
+        scene.background = Arc::new(SolidBackground { color: Color::zero() });
+        let mut sampler = SamplerKind::Independent.create(7, 1);
+        let expected = Color::from_tuple((4.0, 2.0, 1.0));
+        let outside = Ray::new(Color::from_tuple((0.0, 0.0, 5.0)), Color::from_tuple((0.0, 0.0, -1.0)));
+        let inside = Ray::new(Color::zero(), Color::from_tuple((0.3, 0.2, -1.0)));
+        for r in [outside, inside].iter() {
+            sampler.start_sample(0, 0);
+            assert_eq!(raycolor(r, &scene, 8, 3, &mut *sampler, None), expected);
+        }
+        // and nothing where the ray misses
+        let away = Ray::new(Color::from_tuple((0.0, 0.0, 5.0)), Color::from_tuple((0.0, 0.0, 1.0)));
+        assert_eq!(raycolor(&away, &scene, 8, 3, &mut *sampler, None), Color::zero());
+    }
+
+    // One diffuse bounce off a coloured ground under a white sky returns exactly the albedo.
+    // With roulette from the first bounce paths survive with the largest channel and come back
+    // brighter, which has to average out to the same thing
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        let mut world = World::new();
+        world.addMat("ground".to_string(), matTypes::lambert, (0.8, 0.4, 0.2), 0.0, 1.0);
+        world.addSphere((0.0, -1000.0, 0.0), 1000.0, "ground".to_string());
+        let mut scene = Scene::new(world, CameraDescription::default().build(1.0));
+        scene.background = Arc::new(SolidBackground { color: Color::one() });
+        let albedo = Color::from_tuple((0.8, 0.4, 0.2));
+        let r = Ray::new(Color::from_tuple((0.0, 1.0, 0.0)), Color::from_tuple((0.0, -1.0, 0.0)));
+        let mut sampler = SamplerKind::Independent.create(3, 1);
+        assert_eq!(raycolor(&r, &scene, 8, 8, &mut *sampler, None), albedo);
+
+        let n = 20000;
+        let mut sum = Color::zero();
+        let mut survived = 0;
+        for i in 0..n {
+            sampler.start_sample(0, i);
+            let c = raycolor(&r, &scene, 8, 0, &mut *sampler, None);
+            if c.x > 0.0 {
+                survived += 1;
+                assert!((c.x - 1.0).abs() < 1e-12 && (c.z - 0.25).abs() < 1e-12);
+            }
+            sum = sum + c;
+        }
+        let mean = &sum * (1.0 / n as f64);
+        assert!((mean - albedo).magnitude() < 0.02, "{:?}", mean);
+        assert!((survived as f64 / n as f64 - 0.8).abs() < 0.02);
+    }
+
+    // A grey floor lit by a sphere light straight above the point the ray hits. The reflected
+    // radiance there is albedo * L * sin^2 of the cone's half angle, here 0.5 * 16 / 16. Light
+    // samples and bounces that land on the light share it through MIS, and together they have to
+    // add up to that
+    #[test]
+    fn light_sampling_and_bounces_add_up_to_the_sphere_light() {
+        let mut world = World::new();
+        world.addMat("ground".to_string(), matTypes::lambert, (0.5, 0.5, 0.5), 0.0, 1.0);
+        world.addMat("light".to_string(), matTypes::emissive, (1.0, 1.0, 1.0), 16.0, 1.0);
+        world.addSphere((0.0, -1000.0, 0.0), 1000.0, "ground".to_string());
+        world.addSphere((0.0, 2.0, 0.0), 0.5, "light".to_string());
+        let mut scene = Scene::new(world, CameraDescription::default().build(1.0));
+        scene.background = Arc::new(SolidBackground { color: Color::zero() });
+        let r = Ray::new(Color::from_tuple((1.0, 1.0, 0.0)), Color::from_tuple((-1.0, -1.0, 0.0)));
+        let mut sampler = SamplerKind::Independent.create(11, 1);
+        let n = 20000;
+        let mut sum = 0.0;
+        for i in 0..n {
+            sampler.start_sample(0, i);
+            sum += raycolor(&r, &scene, 8, 100, &mut *sampler, None).y;
+        }
+        let mean = sum / n as f64;
+        assert!((mean - 0.5).abs() < 0.01, "{}", mean);
+    }
+
+    // Glows and bounces straight back towards the centre, losing nothing
+    struct Echo {}
+    impl crate::material::Material for Echo {
+        fn scatter(&self, _r: &Ray, hit: &HitInfo, _sampler: &mut dyn Sampler) -> Option<crate::material::scatter_result> {
+            Some(crate::material::scatter_result {
+                attenuation: Color::one(),
+                ray: Ray::new(hit.p, &hit.p * -1.0),
+                pdf: 1.0,
+                specular: true,
+            })
+        }
+        fn emitted(&self, _r: &Ray, _hit: &HitInfo) -> Color<f64> {
+            Color::one()
+        }
+        fn albedo(&self, _hit: &HitInfo) -> Color<f64> {
+            Color::one()
+        }
+    }
+
+    // Inside a sphere that never absorbs anything, every path runs to max_depth and picks up one
+    // unit of emission per segment
+    #[test]
+    fn paths_stop_at_max_depth() {
+        let mut world = World::new();
+        world.materials.insert("echo".to_string(), Arc::new(Echo {}));
+        world.material_ids.insert("echo".to_string(), 0);
+        world.addSphere((0.0, 0.0, 0.0), 1.0, "echo".to_string());
+        let mut scene = Scene::new(world, CameraDescription::default().build(1.0));
+        scene.background = Arc::new(SolidBackground { color: Color::zero() });
+        let mut sampler = SamplerKind::Independent.create(5, 1);
+        let r = Ray::new(Color::zero(), Color::from_tuple((0.0, 0.0, -1.0)));
+        for &depth in [0u32, 1, 2, 7, 50].iter() {
+            // roulette never kills a path whose throughput stays at one
+            for &rr_depth in [0u32, 1000].iter() {
+                let c = raycolor(&r, &scene, depth, rr_depth, &mut *sampler, None);
+                assert_eq!(c, Color::from_tuple((depth as f64, depth as f64, depth as f64)));
+            }
+        }
+    }
+
+    #[test]
+    fn renders_into_float_framebuffer() {
+        let mut world = World::new();
+        world.addMat("grey".to_string(), matTypes::lambert, (0.5, 0.5, 0.5), 0.0, 1.0);
+        world.addSphere((0.0, 0.0, 0.0), 1.0, "grey".to_string());
+        let settings = RenderSettings {
+            width: 8,
+            height: 4,
+            samples: 2,
+            max_depth: 4,
+            rr_depth: 2,
+            seed: 1,
+        };
+        let scene = Scene::new(world, CameraDescription::default().build(settings.aspect_ratio()));
+        let fb = Renderer::new(settings).render(&scene);
+        assert_eq!(fb.pixels.len(), 32);
+        // corners look past the sphere into the sky
+        assert!(fb.get(0, 0).x > 0.4);
+        assert!(fb.pixels.iter().all(|c| c.x.is_finite() && c.x >= 0.0));
+    }
+
+    #[test]
+    fn ldr_conversion_uses_srgb() {
+        let mut fb = Framebuffer::new(3, 1);
+        fb.pixels = vec![Color::zero(), Color::from_tuple((0.5, 0.5, 0.5)), Color::from_tuple((2.0, 1.0, -1.0))];
+        let img = fb.to_ldr(8, false);
+        assert_eq!(img.pixels, vec![[0, 0, 0], [188, 188, 188], [255, 255, 0]]);
+        assert_eq!(fb.to_ldr(16, false).pixels[2], [65535, 65535, 0]);
+    }
+
+    #[test]
+    fn same_seed_renders_identically() {
+        let settings = RenderSettings {
+            width: 6,
+            height: 4,
+            samples: 8,
+            max_depth: 8,
+            rr_depth: 2,
+            seed: 42,
+        };
+        let render = |settings: RenderSettings| {
+            let mut world = World::new();
+            world.addMat("grey".to_string(), matTypes::lambert, (0.5, 0.5, 0.5), 0.0, 1.0);
+            world.addMat("glass".to_string(), matTypes::dialectric, (1.0, 1.0, 1.0), 0.1, 1.5);
+            world.addSphere((0.0, -100.5, 0.0), 100.0, "grey".to_string());
+            world.addSphere((0.0, 0.0, 0.0), 1.0, "glass".to_string());
+            let scene = Scene::new(world, CameraDescription::default().build(settings.aspect_ratio()));
+            Renderer::new(settings).render(&scene).pixels
+        };
+        let a = render(settings);
+        assert_eq!(a, render(settings));
+        assert_ne!(a, render(RenderSettings { seed: 43, ..settings }));
+    }
+
+    #[test]
+    fn progressive_passes_add_up_to_the_full_render() {
+        let settings = RenderSettings {
+            width: 6,
+            height: 4,
+            samples: 4,
+            max_depth: 8,
+            rr_depth: 2,
+            seed: 3,
+        };
+        let mut world = World::new();
+        world.addMat("grey".to_string(), matTypes::lambert, (0.5, 0.5, 0.5), 0.0, 1.0);
+        world.addSphere((0.0, 0.0, 0.0), 1.0, "grey".to_string());
+        let scene = Scene::new(world, CameraDescription::default().build(settings.aspect_ratio()));
+        let mut renderer = Renderer::new(settings);
+        let full = renderer.render(&scene).pixels;
+        // odd tile sizes and order must not change a thing
+        renderer.tile_size = 3;
+        renderer.tile_order = TileOrder::Hilbert;
+        assert_eq!(renderer.render(&scene).pixels, full);
+        let (tx, rx) = std::sync::mpsc::channel();
+        renderer.render_progressive(&scene, &tx, &AtomicBool::new(false));
+        drop(tx);
+        let mut film = Film::new(6, 4);
+        for event in rx.iter() {
+            if let TileEvent::Finished { result, .. } = event {
+                film.add_tile(&result);
+            }
+        }
+        assert_eq!(film.resolve().pixels, full);
+    }
+
+    #[test]
+    fn filters_keep_flat_images_flat() {
+        let settings = RenderSettings {
+            width: 10,
+            height: 7,
+            samples: 4,
+            max_depth: 2,
+            rr_depth: 2,
+            seed: 1,
+        };
+        let mut world = World::new();
+        world.addMat("grey".to_string(), matTypes::lambert, (0.5, 0.5, 0.5), 0.0, 1.0);
+        // behind the camera, so every ray sees the background
+        world.addSphere((100.0, 20.0, 30.0), 1.0, "grey".to_string());
+        let mut scene = Scene::new(world, CameraDescription::default().build(settings.aspect_ratio()));
+        scene.background = Arc::new(SolidBackground {
+            color: Color::from_tuple((0.25, 0.5, 1.0)),
+        });
+        for kind in FilterKind::ALL.iter() {
+            let mut renderer = Renderer::new(settings);
+            renderer.filter = Filter::new(*kind);
+            renderer.tile_size = 4;
+            let fb = renderer.render(&scene);
+            for c in fb.pixels.iter() {
+                assert!((c.y - 0.5).abs() < 1e-9 && (c.z - 1.0).abs() < 1e-9, "{:?} {}", kind, c);
+            }
+        }
+    }
+
+    #[test]
+    fn aovs_record_the_first_hit() {
+        let settings = RenderSettings {
+            width: 16,
+            height: 8,
+            samples: 4,
+            max_depth: 4,
+            rr_depth: 2,
+            seed: 2,
+        };
+        let mut world = World::new();
+        world.addMat("grey".to_string(), matTypes::lambert, (0.5, 0.5, 0.5), 0.0, 1.0);
+        world.addMat("red".to_string(), matTypes::lambert, (0.8, 0.1, 0.1), 0.0, 1.0);
+        world.addSphere((0.0, -100.5, 0.0), 100.0, "grey".to_string());
+        world.addSphere((0.0, 0.0, 0.0), 1.0, "red".to_string());
+        let desc = CameraDescription::default();
+        let scene = Scene::new(world, desc.build(settings.aspect_ratio()));
+        let mut renderer = Renderer::new(settings);
+        let plain = renderer.render(&scene).pixels;
+        renderer.aovs = true;
+        let film = renderer.render_film(&scene, |_, _| {});
+        // recording them leaves the beauty alone
+        assert_eq!(film.resolve().pixels, plain);
+
+        let sky = film.aovs[0];
+        assert_eq!((sky.count, sky.hits, sky.ids), (4, 0, None));
+        assert_eq!(sky.depth(), std::f64::INFINITY);
+        let ball = film.aovs[4 * 16 + 8];
+        assert_eq!((ball.hits, ball.ids), (4, Some((1, 1))));
+        // from the lens, which is only ever a fraction of the aperture away from the camera's origin
+        assert!((ball.depth() - (ball.position() - desc.origin).magnitude()).abs() < desc.aperture);
+        assert!(ball.depth() > desc.origin.magnitude() - 1.0 && ball.depth() < desc.origin.magnitude());
+        assert!((ball.albedo() - Color::from_tuple((0.8, 0.1, 0.1))).magnitude() < 1e-9);
+        // a unit sphere at the origin, where normals and hit points are the same thing
+        assert!((ball.normal() - ball.position()).magnitude() < 1e-9);
+        let ground = film.aovs[7 * 16];
+        assert_eq!(ground.ids, Some((0, 0)));
+        assert!(ground.normal().y > 0.99 && ground.position().y < -0.5);
+    }
+
+    #[test]
+    fn adaptive_sampling_stops_converged_pixels() {
+        let settings = RenderSettings {
+            width: 12,
+            height: 8,
+            samples: 64,
+            max_depth: 8,
+            rr_depth: 2,
+            seed: 5,
+        };
+        let mut world = World::new();
+        world.addMat("grey".to_string(), matTypes::lambert, (0.5, 0.5, 0.5), 0.0, 1.0);
+        world.addSphere((0.0, 0.0, 0.0), 1.0, "grey".to_string());
+        let scene = Scene::new(world, CameraDescription::default().build(settings.aspect_ratio()));
+        let mut renderer = Renderer::new(settings);
+        renderer.adaptive = Some(AdaptiveSampling {
+            threshold: 0.01,
+            min_samples: 16,
+        });
+        let film = renderer.render_film(&scene, |_, _| {});
+        // the sky barely changes across a pixel, the diffuse sphere needs everything it can get
+        assert_eq!(film.samples[0], 16);
+        assert_eq!(*film.samples.iter().max().unwrap(), 64);
+        assert!(film.samples.iter().all(|s| *s >= 16 && (*s == 64 || *s % ADAPTIVE_BATCH == 0)));
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        renderer.render_progressive(&scene, &tx, &AtomicBool::new(false));
+        drop(tx);
+        let mut progressive = Film::new(12, 8);
+        for event in rx.iter() {
+            if let TileEvent::Finished { result, .. } = event {
+                progressive.add_tile(&result);
+            }
+        }
+        assert_eq!(progressive.samples, film.samples);
+        assert_eq!(progressive.resolve().pixels, film.resolve().pixels);
+    }
+}
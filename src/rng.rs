@@ -0,0 +1,78 @@
+use rand::{Error, RngCore};
+
+// splitmix64 finalizer, mixes seed / pixel / sample indices into well spread stream parameters
+pub fn hash64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// PCG32 (XSH RR). Cheap enough to seed that every sample of every pixel gets its own stream,
+// which keeps renders identical no matter which thread picks up which sample
+#[derive(Debug, Clone)]
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+impl Pcg32 {
+    pub fn new(seed: u64, stream: u64) -> Pcg32 {
+        let mut rng = Pcg32 {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+    pub fn for_sample(seed: u64, pixel: u64, sample: u64) -> Pcg32 {
+        Pcg32::new(
+            hash64(hash64(seed ^ hash64(pixel)) ^ sample),
+            hash64(seed.wrapping_add(pixel)),
+        )
+    }
+}
+impl RngCore for Pcg32 {
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+    fn next_u64(&mut self) -> u64 {
+        let lo = self.next_u32() as u64;
+        let hi = self.next_u32() as u64;
+        (hi << 32) | lo
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_streams_are_repeatable_and_distinct() {
+        let mut a = Pcg32::for_sample(7, 12, 3);
+        let mut b = Pcg32::for_sample(7, 12, 3);
+        let first: Vec<u32> = (0..8).map(|_| a.next_u32()).collect();
+        assert_eq!(first, (0..8).map(|_| b.next_u32()).collect::<Vec<u32>>());
+        let mut c = Pcg32::for_sample(7, 12, 4);
+        assert_ne!(first, (0..8).map(|_| c.next_u32()).collect::<Vec<u32>>());
+        let mut d = Pcg32::for_sample(8, 12, 3);
+        assert_ne!(first, (0..8).map(|_| d.next_u32()).collect::<Vec<u32>>());
+    }
+}
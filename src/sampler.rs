@@ -0,0 +1,330 @@
+use crate::rng::{hash64, Pcg32};
+use rand::Rng;
+
+// Where every random decision of a path comes from. The camera, lights and materials ask for one
+// or two dimensions at a time in the same order for every sample of a pixel, so a sampler can
+// spread each dimension's values evenly over the pixel's samples instead of leaving it to chance
+pub trait Sampler {
+    // Moves to sample `index` of `pixel` and back to the first dimension
+    fn start_sample(&mut self, pixel: u64, index: u32);
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+impl SamplerKind {
+    pub const ALL: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        SamplerKind::ALL.iter().cloned().find(|k| k.name() == name)
+    }
+    // samples_per_pixel is what the stratified sampler divides each dimension into
+    pub fn create(self, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler + Send> {
+        let state = SampleState {
+            seed: seed,
+            pixel: 0,
+            index: 0,
+            dim: 0,
+        };
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler {
+                seed: seed,
+                rng: Pcg32::new(seed, 0),
+            }),
+            SamplerKind::Stratified => Box::new(StratifiedSampler {
+                state: state,
+                samples: samples_per_pixel.max(1),
+            }),
+            SamplerKind::Halton => Box::new(HaltonSampler { state: state }),
+            SamplerKind::Sobol => Box::new(SobolSampler { state: state }),
+        }
+    }
+}
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - 1e-16 * 2.0;
+
+fn to_unit(x: u32) -> f64 {
+    x as f64 / 4_294_967_296.0
+}
+fn hash_to_unit(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Where the deterministic samplers are, and hashes that stay put for a pixel's dimension across
+// all of its samples (for permutations and scrambles) or change every sample (for jitter)
+struct SampleState {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dim: u32,
+}
+impl SampleState {
+    fn start(&mut self, pixel: u64, index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dim = 0;
+    }
+    fn next_dim(&mut self) -> u32 {
+        self.dim += 1;
+        self.dim - 1
+    }
+    fn dim_hash(&self, dim: u32) -> u64 {
+        hash64(hash64(self.seed ^ hash64(self.pixel)) ^ dim as u64)
+    }
+    fn sample_hash(&self, dim: u32) -> u64 {
+        hash64(self.dim_hash(dim) ^ hash64(self.index as u64 + 1))
+    }
+}
+
+// Plain white noise, every sample gets its own PCG stream
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Pcg32,
+}
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, pixel: u64, index: u32) {
+        self.rng = Pcg32::for_sample(self.seed, pixel, index as u64);
+    }
+    fn get_1d(&mut self) -> f64 {
+        self.rng.gen::<f64>()
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Kensler's hashed permutation of 0..l, so strata can be shuffled per pixel and dimension
+// without storing anything
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+// Jittered strata, shuffled independently for every dimension. 2D requests get a jittered grid
+// when the sample count is a square and Latin hypercube strata otherwise
+pub struct StratifiedSampler {
+    state: SampleState,
+    samples: u32,
+}
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, pixel: u64, index: u32) {
+        self.state.start(pixel, index);
+    }
+    fn get_1d(&mut self) -> f64 {
+        let dim = self.state.next_dim();
+        let n = self.samples;
+        let stratum = permute(self.state.index % n, n, self.state.dim_hash(dim) as u32);
+        (stratum as f64 + hash_to_unit(self.state.sample_hash(dim))) / n as f64
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dim = self.state.next_dim();
+        let n = self.samples;
+        let i = self.state.index % n;
+        let h = self.state.dim_hash(dim);
+        let jitter = self.state.sample_hash(dim);
+        let (jx, jy) = (hash_to_unit(jitter), hash_to_unit(hash64(jitter)));
+        let k = (n as f64).sqrt().round() as u32;
+        if k * k == n {
+            let cell = permute(i, n, h as u32);
+            (((cell % k) as f64 + jx) / k as f64, ((cell / k) as f64 + jy) / k as f64)
+        } else {
+            let (sx, sy) = (permute(i, n, h as u32), permute(i, n, (h >> 32) as u32));
+            ((sx as f64 + jx) / n as f64, (sy as f64 + jy) / n as f64)
+        }
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+// Radical inverse with every digit position run through its own random permutation. Keeps the
+// stratification but stops big bases from bunching a handful of samples into one corner
+fn scrambled_radical_inverse(base: u32, mut i: u32, seed: u64) -> f64 {
+    let inv = 1.0 / base as f64;
+    let (mut digits, mut scale, mut level) = (0.0, inv, 0);
+    // keeps going past the index's own digits, the permuted zeros fill in the low end
+    while scale > 1e-15 {
+        let digit = permute(i % base, base, hash64(seed ^ level) as u32);
+        digits += digit as f64 * scale;
+        i /= base;
+        scale *= inv;
+        level += 1;
+    }
+    digits
+}
+
+// Halton sequence over the pixel's samples, one prime base per dimension, digit scrambled per
+// pixel so neighbours don't share points. Paths deeper than the prime table fall back to hashing
+pub struct HaltonSampler {
+    state: SampleState,
+}
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, pixel: u64, index: u32) {
+        self.state.start(pixel, index);
+    }
+    fn get_1d(&mut self) -> f64 {
+        let dim = self.state.next_dim();
+        match PRIMES.get(dim as usize) {
+            Some(base) => {
+                scrambled_radical_inverse(*base, self.state.index, self.state.dim_hash(dim)).min(ONE_MINUS_EPSILON)
+            }
+            None => hash_to_unit(self.state.sample_hash(dim)),
+        }
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Second Sobol dimension, the first is just the index bit reversed
+fn sobol_dim1(mut i: u32) -> u32 {
+    let (mut r, mut v) = (0, 1u32 << 31);
+    while i > 0 {
+        if i & 1 == 1 {
+            r ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    r
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+// Base 2 Owen scrambling, from Burley's "Practical Hash-based Owen Scrambling"
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// Owen scrambled Sobol (0,2)-sequence, padded: every 1D/2D request shuffles the sample order with
+// its own seed so dimensions don't correlate, and scrambles the points themselves
+pub struct SobolSampler {
+    state: SampleState,
+}
+impl SobolSampler {
+    fn point(&mut self) -> (u32, u32, u64) {
+        let dim = self.state.next_dim();
+        let h = self.state.dim_hash(dim);
+        let i = nested_uniform_scramble(self.state.index, h as u32);
+        (i.reverse_bits(), sobol_dim1(i), h)
+    }
+}
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, pixel: u64, index: u32) {
+        self.state.start(pixel, index);
+    }
+    fn get_1d(&mut self) -> f64 {
+        let (x, _, h) = self.point();
+        to_unit(nested_uniform_scramble(x, (h >> 32) as u32))
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (x, y, h) = self.point();
+        let h2 = hash64(h);
+        (
+            to_unit(nested_uniform_scramble(x, (h >> 32) as u32)),
+            to_unit(nested_uniform_scramble(y, h2 as u32)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16 samples of a pixel: every dimension hits each of 16 intervals once, and the stratified
+    // and Sobol 2D points each land in their own cell of a 4x4 grid
+    #[test]
+    fn samples_are_stratified() {
+        for kind in SamplerKind::ALL.iter().skip(1) {
+            let mut sampler = kind.create(9, 16);
+            let (mut first, mut pairs, mut later) = (vec![], vec![], vec![]);
+            for i in 0..16 {
+                sampler.start_sample(77, i);
+                first.push(sampler.get_1d());
+                pairs.push(sampler.get_2d());
+                later.push(sampler.get_1d());
+            }
+            // Halton's later dimensions have bases that don't divide 16
+            if *kind == SamplerKind::Halton {
+                later = first.clone();
+            }
+            for values in [first, later].iter() {
+                let mut cells: Vec<usize> = values.iter().map(|v| (v * 16.0) as usize).collect();
+                cells.sort();
+                assert_eq!(cells, (0..16).collect::<Vec<usize>>(), "{:?}", kind);
+            }
+            if *kind != SamplerKind::Halton {
+                let mut cells: Vec<usize> = pairs
+                    .iter()
+                    .map(|(x, y)| (x * 4.0) as usize + 4 * (y * 4.0) as usize)
+                    .collect();
+                cells.sort();
+                assert_eq!(cells, (0..16).collect::<Vec<usize>>(), "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn permute_is_a_permutation() {
+        for &l in [1, 5, 16, 100].iter() {
+            let mut p: Vec<u32> = (0..l).map(|i| permute(i, l, 0xdead_beef)).collect();
+            p.sort();
+            assert_eq!(p, (0..l).collect::<Vec<u32>>());
+        }
+    }
+}
@@ -0,0 +1,814 @@
+use crate::background::{Background, EnvironmentMap, GradientBackground, SolidBackground};
+use crate::camera::Camera;
+use crate::render::{MAX_IMAGE_SIDE, MAX_PATH_DEPTH, MAX_SAMPLES};
+use crate::transform::Transform;
+use crate::vectors::Vector3;
+use crate::vectors::Vector3 as Color;
+use crate::world::matTypes;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Line based scene files. One statement per line, '#' starts a comment:
+//
+//   settings width=1200 height=800 samples=500 max_depth=50 rr_depth=3
+//   background color=0,0,0
+//   background gradient bottom=1,1,1 top=0.5,0.7,1
+//   background envmap file=studio.hdr rotate=90 intensity=1.5
+//   camera origin=10,2,3 target=0,0,0 vfov=30 aperture=0.1 focus=10
+//   material grey lambert color=0.5,0.5,0.5
+//   material glass dialectric color=1,1,1 fuzz=0.001 ior=1.5
+//   sphere center=0,-1000,0 radius=1000 material=grey
+//   tri p0=0,0,0 p1=1,0,0 p2=0,1,0 material=grey
+//   mesh file=monkey.obj material=glass translate=4,0.2,0 rotate=0,90,0 scale=1 cull_backfaces=true smooth=60
+//
+// Mesh and environment map paths are relative to the scene file. Meshes without vertex normals
+// are flat shaded, unless smooth gives a crease angle in degrees to generate smooth normals with.
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, std::io::Error),
+    Parse {
+        line: usize,
+        column: usize,
+        key: Option<String>,
+        message: String,
+    },
+}
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(path, e) => write!(f, "couldn't read {}: {}", path.display(), e),
+            SceneError::Parse {
+                line,
+                column,
+                key: Some(key),
+                message,
+            } => write!(f, "{}:{}: {} (key '{}')", line, column, message, key),
+            SceneError::Parse {
+                line,
+                column,
+                key: None,
+                message,
+            } => write!(f, "{}:{}: {}", line, column, message),
+        }
+    }
+}
+impl std::error::Error for SceneError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialDescription {
+    pub name: String,
+    pub kind: matTypes,
+    pub color: Color<f64>,
+    pub fuzz: f64,
+    pub ior: f64,
+    pub emission: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectDescription {
+    Sphere {
+        center: Vector3<f64>,
+        radius: f64,
+        material: String,
+    },
+    Tri {
+        p0: Vector3<f64>,
+        p1: Vector3<f64>,
+        p2: Vector3<f64>,
+        material: String,
+    },
+    Mesh {
+        file: PathBuf,
+        material: String,
+        transform: Transform,
+        cull_backfaces: bool,
+        smooth: Option<f64>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackgroundDescription {
+    Solid(Color<f64>),
+    Gradient {
+        bottom: Color<f64>,
+        top: Color<f64>,
+    },
+    EnvMap {
+        file: PathBuf,
+        rotate: f64,
+        intensity: f64,
+    },
+}
+impl BackgroundDescription {
+    pub fn build(&self) -> Result<Arc<dyn Background + Send + Sync>, String> {
+        Ok(match self {
+            BackgroundDescription::Solid(color) => Arc::new(SolidBackground { color: *color }),
+            BackgroundDescription::Gradient { bottom, top } => Arc::new(GradientBackground {
+                bottom: *bottom,
+                top: *top,
+            }),
+            BackgroundDescription::EnvMap {
+                file,
+                rotate,
+                intensity,
+            } => Arc::new(
+                EnvironmentMap::load(file, *rotate, *intensity).map_err(|e| {
+                    format!("couldn't load environment map {}: {}", file.display(), e)
+                })?,
+            ),
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CameraDescription {
+    pub origin: Vector3<f64>,
+    pub target: Vector3<f64>,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_distance: f64,
+}
+impl Default for CameraDescription {
+    fn default() -> CameraDescription {
+        CameraDescription {
+            origin: Vector3::from_tuple((10.0, 2.0, 3.0)),
+            target: Vector3::zero(),
+            vfov: 30.0,
+            aperture: 0.1,
+            focus_distance: 10.0,
+        }
+    }
+}
+impl CameraDescription {
+    pub fn build(&self, aspect_ratio: f64) -> Camera {
+        Camera::new(
+            self.origin,
+            self.target,
+            self.vfov,
+            aspect_ratio,
+            self.aperture,
+            self.focus_distance,
+        )
+    }
+    // The camera statement that reproduces this camera, for pasting back into a scene file
+    pub fn to_scene_line(&self) -> String {
+        let v = |v: &Vector3<f64>| format!("{:.4},{:.4},{:.4}", v.x, v.y, v.z);
+        format!(
+            "camera origin={} target={} vfov={:.3} aperture={:.4} focus={:.4}",
+            v(&self.origin),
+            v(&self.target),
+            self.vfov,
+            self.aperture,
+            self.focus_distance
+        )
+    }
+    // Moves origin and target together along the view direction, the camera's right and world up
+    pub fn fly(&mut self, forward: f64, right: f64, up: f64) {
+        let f = (self.target - self.origin).normalized();
+        let r = f.cross(&Vector3::up()).normalized();
+        let step = &(&(forward * f) + &(right * r)) + &(up * Vector3::up());
+        self.origin = self.origin + step;
+        self.target = self.target + step;
+    }
+    // Swings the origin around the target, yaw about world up and pitch towards the poles (radians).
+    // Pitch stops short of straight up/down where the camera basis falls apart
+    pub fn orbit(&mut self, yaw: f64, pitch: f64) {
+        let offset = self.origin - self.target;
+        let dist = offset.magnitude();
+        let polar = (offset.y / dist).acos() - pitch;
+        let polar = polar.max(0.01).min(std::f64::consts::PI - 0.01);
+        let azimuth = offset.z.atan2(offset.x) + yaw;
+        self.origin = self.target
+            + Vector3::from_tuple((
+                dist * polar.sin() * azimuth.cos(),
+                dist * polar.cos(),
+                dist * polar.sin() * azimuth.sin(),
+            ));
+    }
+    // Narrows (factor < 1) or widens the field of view
+    pub fn zoom(&mut self, factor: f64) {
+        self.vfov = (self.vfov * factor).max(1.0).min(150.0);
+    }
+}
+
+// Anything left as None falls back to the command line / built in defaults
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct SettingsDescription {
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples: Option<u32>,
+    pub max_depth: Option<u32>,
+    pub rr_depth: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SceneDescription {
+    pub settings: SettingsDescription,
+    pub camera: CameraDescription,
+    pub background: Option<BackgroundDescription>,
+    pub materials: Vec<MaterialDescription>,
+    pub objects: Vec<ObjectDescription>,
+}
+
+pub fn load_scene(path: &Path) -> Result<SceneDescription, SceneError> {
+    let text = fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_path_buf(), e))?;
+    let mut desc = parse_scene(&text)?;
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    for o in desc.objects.iter_mut() {
+        if let ObjectDescription::Mesh { file, .. } = o {
+            if file.is_relative() {
+                *file = base.join(&file);
+            }
+        }
+    }
+    if let Some(BackgroundDescription::EnvMap { file, .. }) = &mut desc.background {
+        if file.is_relative() {
+            *file = base.join(&file);
+        }
+    }
+    Ok(desc)
+}
+
+struct Token {
+    text: String,
+    column: usize,
+}
+
+fn error(line: usize, column: usize, key: Option<&str>, message: String) -> SceneError {
+    SceneError::Parse {
+        line,
+        column,
+        key: key.map(|k| k.to_string()),
+        message,
+    }
+}
+
+// Splits on whitespace, honours "quoted strings" and drops comments
+fn tokenize(text: &str, line: usize) -> Result<Vec<Token>, SceneError> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut start = 0;
+    let mut quote_start = None;
+    for (i, c) in text.chars().enumerate() {
+        let column = i + 1;
+        if quote_start.is_some() {
+            if c == '"' {
+                quote_start = None;
+            } else {
+                current.push(c);
+            }
+            continue;
+        }
+        match c {
+            '#' => break,
+            '"' => {
+                if current.is_empty() {
+                    start = column;
+                }
+                quote_start = Some(column);
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    tokens.push(Token {
+                        text: current.clone(),
+                        column: start,
+                    });
+                    current.clear();
+                }
+            }
+            c => {
+                if current.is_empty() {
+                    start = column;
+                }
+                current.push(c);
+            }
+        }
+    }
+    if let Some(column) = quote_start {
+        return Err(error(line, column, None, "unterminated string".to_string()));
+    }
+    if !current.is_empty() {
+        tokens.push(Token {
+            text: current,
+            column: start,
+        });
+    }
+    Ok(tokens)
+}
+
+struct Field {
+    key: String,
+    key_column: usize,
+    value: String,
+    value_column: usize,
+}
+
+// The key=value pairs of one statement. Each take_* consumes a key, finish() rejects leftovers
+struct Fields {
+    line: usize,
+    column: usize,
+    statement: String,
+    fields: Vec<Field>,
+}
+impl Fields {
+    fn new(line: usize, statement: &Token, tokens: &[Token]) -> Result<Fields, SceneError> {
+        let mut fields: Vec<Field> = Vec::new();
+        for t in tokens {
+            let eq = match t.text.find('=') {
+                Some(eq) if eq > 0 => eq,
+                _ => {
+                    return Err(error(
+                        line,
+                        t.column,
+                        None,
+                        format!("expected key=value in '{}', found '{}'", statement.text, t.text),
+                    ))
+                }
+            };
+            let key = &t.text[..eq];
+            if let Some(prev) = fields.iter().find(|f| f.key == key) {
+                return Err(error(
+                    line,
+                    t.column,
+                    Some(key),
+                    format!("duplicate key, already set at column {}", prev.key_column),
+                ));
+            }
+            fields.push(Field {
+                key: key.to_string(),
+                key_column: t.column,
+                value: t.text[eq + 1..].to_string(),
+                value_column: t.column + t.text[..=eq].chars().count(),
+            });
+        }
+        Ok(Fields {
+            line,
+            column: statement.column,
+            statement: statement.text.clone(),
+            fields,
+        })
+    }
+    fn take(&mut self, key: &str) -> Option<Field> {
+        let i = self.fields.iter().position(|f| f.key == key)?;
+        Some(self.fields.remove(i))
+    }
+    fn bad_value(&self, f: &Field, message: String) -> SceneError {
+        error(self.line, f.value_column, Some(&f.key), message)
+    }
+    fn missing(&self, key: &str) -> SceneError {
+        error(
+            self.line,
+            self.column,
+            Some(key),
+            format!("'{}' needs a '{}'", self.statement, key),
+        )
+    }
+    fn take_f64(&mut self, key: &str) -> Result<Option<f64>, SceneError> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(f) => match f.value.parse::<f64>() {
+                Ok(v) if v.is_finite() => Ok(Some(v)),
+                _ => Err(self.bad_value(&f, format!("expected a number, found '{}'", f.value))),
+            },
+        }
+    }
+    fn take_positive(&mut self, key: &str) -> Result<Option<f64>, SceneError> {
+        let column = self.fields.iter().find(|f| f.key == key).map(|f| f.value_column);
+        match self.take_f64(key)? {
+            Some(v) if v <= 0.0 => Err(error(
+                self.line,
+                column.unwrap_or(1),
+                Some(key),
+                format!("must be greater than zero, found {}", v),
+            )),
+            v => Ok(v),
+        }
+    }
+    fn take_non_negative(&mut self, key: &str) -> Result<Option<f64>, SceneError> {
+        let column = self.fields.iter().find(|f| f.key == key).map(|f| f.value_column);
+        match self.take_f64(key)? {
+            Some(v) if v < 0.0 => Err(error(
+                self.line,
+                column.unwrap_or(1),
+                Some(key),
+                format!("must not be negative, found {}", v),
+            )),
+            v => Ok(v),
+        }
+    }
+    // Positive and below limit
+    fn take_below(&mut self, key: &str, limit: f64) -> Result<Option<f64>, SceneError> {
+        let column = self.fields.iter().find(|f| f.key == key).map(|f| f.value_column);
+        match self.take_positive(key)? {
+            Some(v) if v >= limit => Err(error(
+                self.line,
+                column.unwrap_or(1),
+                Some(key),
+                format!("must be less than {}, found {}", limit, v),
+            )),
+            v => Ok(v),
+        }
+    }
+    // A whole number in min..=max, the same ranges the command line takes
+    fn take_u32(&mut self, key: &str, min: u32, max: u32) -> Result<Option<u32>, SceneError> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(f) => match f.value.parse::<u32>() {
+                Ok(v) if v >= min && v <= max => Ok(Some(v)),
+                _ => Err(self.bad_value(
+                    &f,
+                    format!("expected a whole number from {} to {}, found '{}'", min, max, f.value),
+                )),
+            },
+        }
+    }
+    fn take_vec3(&mut self, key: &str) -> Result<Option<Vector3<f64>>, SceneError> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(f) => {
+                let parts: Vec<&str> = f.value.split(',').collect();
+                if parts.len() != 3 {
+                    return Err(self.bad_value(
+                        &f,
+                        format!("expected three comma separated numbers, found '{}'", f.value),
+                    ));
+                }
+                let mut v = [0.0; 3];
+                for (i, p) in parts.iter().enumerate() {
+                    v[i] = match p.trim().parse::<f64>() {
+                        Ok(n) if n.is_finite() => n,
+                        _ => {
+                            return Err(self.bad_value(
+                                &f,
+                                format!("'{}' is not a number", p),
+                            ))
+                        }
+                    };
+                }
+                Ok(Some(Vector3::from_tuple((v[0], v[1], v[2]))))
+            }
+        }
+    }
+    // "scale=2" is shorthand for "scale=2,2,2"
+    fn take_scale(&mut self, key: &str) -> Result<Option<Vector3<f64>>, SceneError> {
+        let uniform = match self.fields.iter().find(|f| f.key == key) {
+            Some(f) => !f.value.contains(','),
+            None => return Ok(None),
+        };
+        let v = if uniform {
+            self.take_f64(key)?.map(|s| s * Vector3::one())
+        } else {
+            self.take_vec3(key)?
+        };
+        Ok(v)
+    }
+    fn take_bool(&mut self, key: &str) -> Result<Option<bool>, SceneError> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(f) => match f.value.as_str() {
+                "true" => Ok(Some(true)),
+                "false" => Ok(Some(false)),
+                _ => Err(self.bad_value(&f, format!("expected true or false, found '{}'", f.value))),
+            },
+        }
+    }
+    fn take_str(&mut self, key: &str) -> Option<Field> {
+        self.take(key)
+    }
+    fn finish(self) -> Result<(), SceneError> {
+        match self.fields.first() {
+            None => Ok(()),
+            Some(f) => Err(error(
+                self.line,
+                f.key_column,
+                Some(&f.key),
+                format!("unknown key for '{}'", self.statement),
+            )),
+        }
+    }
+}
+
+pub fn parse_scene(text: &str) -> Result<SceneDescription, SceneError> {
+    let mut desc = SceneDescription::default();
+    let mut settings_line: Option<usize> = None;
+    let mut camera_line: Option<usize> = None;
+    let mut background_line: Option<usize> = None;
+    // name -> line it was declared on
+    let mut material_lines: HashMap<String, usize> = HashMap::new();
+
+    for (i, raw) in text.lines().enumerate() {
+        let line = i + 1;
+        let tokens = tokenize(raw, line)?;
+        let (keyword, rest) = match tokens.split_first() {
+            Some(split) => split,
+            None => continue,
+        };
+        match keyword.text.as_str() {
+            "settings" => {
+                if let Some(prev) = settings_line {
+                    return Err(error(
+                        line,
+                        keyword.column,
+                        None,
+                        format!("settings already defined on line {}", prev),
+                    ));
+                }
+                settings_line = Some(line);
+                let mut f = Fields::new(line, keyword, rest)?;
+                let s = &mut desc.settings;
+                if let Some(w) = f.take_u32("width", 1, MAX_IMAGE_SIDE)? {
+                    s.width = Some(w as usize);
+                }
+                if let Some(h) = f.take_u32("height", 1, MAX_IMAGE_SIDE)? {
+                    s.height = Some(h as usize);
+                }
+                s.samples = f.take_u32("samples", 1, MAX_SAMPLES)?.or(s.samples);
+                s.max_depth = f.take_u32("max_depth", 1, MAX_PATH_DEPTH)?.or(s.max_depth);
+                s.rr_depth = f.take_u32("rr_depth", 0, MAX_PATH_DEPTH)?.or(s.rr_depth);
+                f.finish()?;
+            }
+            "camera" => {
+                if let Some(prev) = camera_line {
+                    return Err(error(
+                        line,
+                        keyword.column,
+                        None,
+                        format!("camera already defined on line {}", prev),
+                    ));
+                }
+                camera_line = Some(line);
+                let mut f = Fields::new(line, keyword, rest)?;
+                let c = &mut desc.camera;
+                c.origin = f.take_vec3("origin")?.unwrap_or(c.origin);
+                c.target = f.take_vec3("target")?.unwrap_or(c.target);
+                c.vfov = f.take_below("vfov", 180.0)?.unwrap_or(c.vfov);
+                c.aperture = f.take_non_negative("aperture")?.unwrap_or(c.aperture);
+                c.focus_distance = f.take_positive("focus")?.unwrap_or(c.focus_distance);
+                f.finish()?;
+            }
+            "background" => {
+                if let Some(prev) = background_line {
+                    return Err(error(
+                        line,
+                        keyword.column,
+                        None,
+                        format!("background already defined on line {}", prev),
+                    ));
+                }
+                background_line = Some(line);
+                // "background color=..." is shorthand for "background solid color=..."
+                let (kind, rest) = match rest.first() {
+                    Some(t) if !t.text.contains('=') => (t, &rest[1..]),
+                    _ => (keyword, rest),
+                };
+                let mut f = Fields::new(line, keyword, rest)?;
+                let d = GradientBackground::default();
+                let background = match kind.text.as_str() {
+                    "background" | "solid" => BackgroundDescription::Solid(
+                        f.take_vec3("color")?.ok_or_else(|| f.missing("color"))?,
+                    ),
+                    "gradient" => BackgroundDescription::Gradient {
+                        bottom: f.take_vec3("bottom")?.unwrap_or(d.bottom),
+                        top: f.take_vec3("top")?.unwrap_or(d.top),
+                    },
+                    "envmap" => BackgroundDescription::EnvMap {
+                        file: match f.take_str("file") {
+                            Some(file) => PathBuf::from(file.value),
+                            None => return Err(f.missing("file")),
+                        },
+                        rotate: f.take_f64("rotate")?.unwrap_or(0.0),
+                        intensity: f.take_non_negative("intensity")?.unwrap_or(1.0),
+                    },
+                    other => {
+                        return Err(error(
+                            line,
+                            kind.column,
+                            None,
+                            format!(
+                                "unknown background type '{}', expected solid, gradient or envmap",
+                                other
+                            ),
+                        ))
+                    }
+                };
+                f.finish()?;
+                desc.background = Some(background);
+            }
+            "material" => {
+                if rest.len() < 2 || rest[0].text.contains('=') || rest[1].text.contains('=') {
+                    return Err(error(
+                        line,
+                        keyword.column,
+                        None,
+                        "expected 'material <name> <type> key=value...'".to_string(),
+                    ));
+                }
+                let name = &rest[0];
+                if let Some(prev) = material_lines.get(&name.text) {
+                    return Err(error(
+                        line,
+                        name.column,
+                        None,
+                        format!("material '{}' already defined on line {}", name.text, prev),
+                    ));
+                }
+                let kind = match rest[1].text.as_str() {
+                    "lambert" => matTypes::lambert,
+                    "metal" => matTypes::metal,
+                    "dialectric" | "dielectric" => matTypes::dialectric,
+                    "emissive" => matTypes::emissive,
+                    "normal" => matTypes::normal,
+                    other => {
+                        return Err(error(
+                            line,
+                            rest[1].column,
+                            None,
+                            format!(
+                                "unknown material type '{}', expected lambert, metal, dialectric, emissive or normal",
+                                other
+                            ),
+                        ))
+                    }
+                };
+                let mut f = Fields::new(line, keyword, &rest[2..])?;
+                let m = MaterialDescription {
+                    name: name.text.clone(),
+                    kind,
+                    color: f.take_vec3("color")?.unwrap_or_else(|| 0.5 * Color::one()),
+                    fuzz: f.take_f64("fuzz")?.unwrap_or(0.0),
+                    ior: f.take_positive("ior")?.unwrap_or(1.5),
+                    emission: f.take_non_negative("emission")?.unwrap_or(1.0),
+                };
+                f.finish()?;
+                material_lines.insert(m.name.clone(), line);
+                desc.materials.push(m);
+            }
+            "sphere" | "tri" | "mesh" => {
+                let mut f = Fields::new(line, keyword, rest)?;
+                let material = match f.take_str("material") {
+                    Some(m) => {
+                        if !material_lines.contains_key(&m.value) {
+                            return Err(f.bad_value(
+                                &m,
+                                format!("no material named '{}' declared above", m.value),
+                            ));
+                        }
+                        m.value
+                    }
+                    None => return Err(f.missing("material")),
+                };
+                let object = match keyword.text.as_str() {
+                    "sphere" => ObjectDescription::Sphere {
+                        center: f.take_vec3("center")?.ok_or_else(|| f.missing("center"))?,
+                        radius: f.take_positive("radius")?.ok_or_else(|| f.missing("radius"))?,
+                        material,
+                    },
+                    "tri" => ObjectDescription::Tri {
+                        p0: f.take_vec3("p0")?.ok_or_else(|| f.missing("p0"))?,
+                        p1: f.take_vec3("p1")?.ok_or_else(|| f.missing("p1"))?,
+                        p2: f.take_vec3("p2")?.ok_or_else(|| f.missing("p2"))?,
+                        material,
+                    },
+                    _ => {
+                        let file = match f.take_str("file") {
+                            Some(file) => PathBuf::from(file.value),
+                            None => return Err(f.missing("file")),
+                        };
+                        let d = Transform::default();
+                        ObjectDescription::Mesh {
+                            file,
+                            material,
+                            transform: Transform {
+                                translate: f.take_vec3("translate")?.unwrap_or(d.translate),
+                                rotate: f.take_vec3("rotate")?.unwrap_or(d.rotate),
+                                scale: f.take_scale("scale")?.unwrap_or(d.scale),
+                            },
+                            cull_backfaces: f.take_bool("cull_backfaces")?.unwrap_or(false),
+                            smooth: f.take_positive("smooth")?,
+                        }
+                    }
+                };
+                f.finish()?;
+                desc.objects.push(object);
+            }
+            other => {
+                return Err(error(
+                    line,
+                    keyword.column,
+                    None,
+                    format!(
+                        "unknown statement '{}', expected settings, camera, background, material, sphere, tri or mesh",
+                        other
+                    ),
+                ))
+            }
+        }
+    }
+    Ok(desc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_err(text: &str) -> (usize, usize, Option<String>) {
+        match parse_scene(text) {
+            Err(SceneError::Parse {
+                line, column, key, ..
+            }) => (line, column, key),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_statements() {
+        let desc = parse_scene(
+            "# comment\n\
+             settings width=64 height=32\n\
+             camera origin=1,2,3 vfov=45\n\
+             material red lambert color=1,0,0\n\
+             sphere center=0,0,0 radius=2 material=red   # trailing\n\
+             mesh file=\"my mesh.obj\" material=red scale=2 cull_backfaces=true smooth=45\n",
+        )
+        .unwrap();
+        assert_eq!(desc.settings.width, Some(64));
+        assert_eq!(desc.settings.samples, None);
+        assert_eq!(desc.camera.origin, Vector3::from_tuple((1.0, 2.0, 3.0)));
+        assert_eq!(desc.camera.vfov, 45.0);
+        assert_eq!(desc.materials[0].kind, matTypes::lambert);
+        assert_eq!(desc.objects.len(), 2);
+        match &desc.objects[1] {
+            ObjectDescription::Mesh {
+                file,
+                transform,
+                cull_backfaces,
+                smooth,
+                ..
+            } => {
+                assert_eq!(file, &PathBuf::from("my mesh.obj"));
+                assert_eq!(transform.scale, 2.0 * Vector3::one());
+                assert!(cull_backfaces);
+                assert_eq!(*smooth, Some(45.0));
+            }
+            o => panic!("expected a mesh, got {:?}", o),
+        }
+    }
+
+    #[test]
+    fn reports_position_and_key() {
+        let text = "material red lambert\nsphere center=0,0,0 radius=abc material=red";
+        assert_eq!(parse_err(text), (2, 28, Some("radius".to_string())));
+        let text = "material red lambert colour=1,0,0";
+        assert_eq!(parse_err(text), (1, 22, Some("colour".to_string())));
+        let text = "sphere center=0,0 radius=1";
+        assert_eq!(parse_err(text), (1, 1, Some("material".to_string())));
+        let text = "material red lambert\n\n  sphere center=0,0 radius=1 material=red";
+        assert_eq!(parse_err(text), (3, 17, Some("center".to_string())));
+        let text = "sphere center=0,0,0 radius=1 material=blue";
+        assert_eq!(parse_err(text), (1, 39, Some("material".to_string())));
+        assert_eq!(parse_err("lights on"), (1, 1, None));
+    }
+
+    #[test]
+    fn rejects_out_of_range_values_and_duplicates() {
+        assert_eq!(parse_err("camera vfov=180"), (1, 13, Some("vfov".to_string())));
+        assert_eq!(parse_err("camera vfov=0"), (1, 13, Some("vfov".to_string())));
+        assert_eq!(parse_err("camera aperture=-0.1"), (1, 17, Some("aperture".to_string())));
+        assert_eq!(parse_err("material sun emissive emission=-2"), (1, 32, Some("emission".to_string())));
+        assert_eq!(parse_err("background envmap file=sky.hdr intensity=-1"), (1, 42, Some("intensity".to_string())));
+        assert_eq!(parse_err("settings width=10\nsettings height=10"), (2, 1, None));
+        assert_eq!(parse_err("settings rr_depth=-1"), (1, 19, Some("rr_depth".to_string())));
+        // the same limits as the command line
+        assert_eq!(parse_err("settings width=70000"), (1, 16, Some("width".to_string())));
+        assert_eq!(parse_err("settings  height=0"), (1, 18, Some("height".to_string())));
+        assert_eq!(parse_err("settings samples=4000000000"), (1, 18, Some("samples".to_string())));
+        assert_eq!(parse_err("settings max_depth=10001"), (1, 20, Some("max_depth".to_string())));
+        let desc = parse_scene("settings width=16384 height=1 samples=1048576 max_depth=10000").unwrap();
+        assert_eq!((desc.settings.width, desc.settings.samples), (Some(16384), Some(1 << 20)));
+        // as on the command line, rr_depth=0 lets roulette start on the first bounce
+        let desc = parse_scene("settings rr_depth=0\ncamera vfov=179 aperture=0").unwrap();
+        assert_eq!(desc.settings.rr_depth, Some(0));
+        assert_eq!(desc.camera.aperture, 0.0);
+    }
+
+    #[test]
+    fn camera_moves_round_trip_through_the_scene_format() {
+        let mut cam = CameraDescription::default();
+        let dist = (cam.origin - cam.target).magnitude();
+        cam.orbit(0.7, 0.3);
+        assert!(((cam.origin - cam.target).magnitude() - dist).abs() < 1e-9);
+        cam.orbit(0.0, 10.0);
+        assert!(cam.origin.y > cam.target.y && (cam.origin.x - cam.target.x).abs() > 1e-3);
+        cam.fly(1.0, -2.0, 0.5);
+        cam.zoom(0.5);
+        let parsed = parse_scene(&cam.to_scene_line()).unwrap().camera;
+        assert!((parsed.origin - cam.origin).magnitude() < 1e-3);
+        assert!((parsed.target - cam.target).magnitude() < 1e-3);
+        assert_eq!(parsed.vfov, 15.0);
+        assert_eq!(parsed.aperture, cam.aperture);
+    }
+}
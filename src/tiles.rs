@@ -0,0 +1,125 @@
+// Buckets of pixels handed out to the render workers. The order only changes what shows up first in
+// the preview, every tile is rendered with the same per sample RNG streams either way
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TileOrder {
+    Scanline,
+    Spiral,
+    Hilbert,
+}
+impl TileOrder {
+    pub const ALL: [TileOrder; 3] = [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert];
+    pub fn name(self) -> &'static str {
+        match self {
+            TileOrder::Scanline => "scanline",
+            TileOrder::Spiral => "spiral",
+            TileOrder::Hilbert => "hilbert",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        TileOrder::ALL.iter().cloned().find(|o| o.name() == name)
+    }
+}
+
+// x, y is the top left corner in framebuffer coordinates, edge tiles are cut short
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+impl Tile {
+    pub fn pixel_count(&self) -> usize {
+        self.width * self.height
+    }
+    // Framebuffer index of the tile's i-th pixel, row by row
+    pub fn pixel_index(&self, i: usize, image_width: usize) -> usize {
+        (self.y + i / self.width) * image_width + self.x + i % self.width
+    }
+}
+
+// Position along a Hilbert curve filling an n x n grid, n a power of two
+fn hilbert_index(n: usize, mut x: usize, mut y: usize) -> usize {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = ((x & s) > 0) as usize;
+        let ry = ((y & s) > 0) as usize;
+        d += s * s * ((3 * rx) ^ ry);
+        // rotate the quadrant so the curve joins up
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x &= s - 1;
+        y &= s - 1;
+        s /= 2;
+    }
+    d
+}
+
+pub fn make_tiles(width: usize, height: usize, size: usize, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let (cols, rows) = ((width + size - 1) / size, (height + size - 1) / size);
+    let mut cells: Vec<(usize, usize)> = (0..rows)
+        .flat_map(|row| (0..cols).map(move |col| (col, row)))
+        .collect();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            // ring by ring out from the middle, going round each ring by angle
+            let (cx, cy) = ((cols as f64 - 1.0) / 2.0, (rows as f64 - 1.0) / 2.0);
+            let key = |&(col, row): &(usize, usize)| {
+                let (dx, dy) = (col as f64 - cx, row as f64 - cy);
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            cells.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+        }
+        TileOrder::Hilbert => {
+            let n = cols.max(rows).next_power_of_two();
+            cells.sort_by_key(|&(col, row)| hilbert_index(n, col, row));
+        }
+    }
+    cells
+        .into_iter()
+        .map(|(col, row)| Tile {
+            x: col * size,
+            y: row * size,
+            width: size.min(width - col * size),
+            height: size.min(height - row * size),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_order_covers_each_pixel_once() {
+        for order in TileOrder::ALL.iter() {
+            let tiles = make_tiles(37, 21, 8, *order);
+            let mut hits = vec![0; 37 * 21];
+            for t in tiles.iter() {
+                for i in 0..t.pixel_count() {
+                    hits[t.pixel_index(i, 37)] += 1;
+                }
+            }
+            assert!(hits.iter().all(|h| *h == 1), "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn hilbert_steps_between_neighbours() {
+        let tiles = make_tiles(64, 64, 8, TileOrder::Hilbert);
+        for pair in tiles.windows(2) {
+            let dist = (pair[0].x as i64 - pair[1].x as i64).abs() + (pair[0].y as i64 - pair[1].y as i64).abs();
+            assert_eq!(dist, 8);
+        }
+        let spiral = make_tiles(40, 40, 8, TileOrder::Spiral);
+        assert_eq!((spiral[0].x, spiral[0].y), (16, 16));
+    }
+}
@@ -0,0 +1,192 @@
+use crate::vectors::Vector3 as Color;
+
+// Squeezes linear scene radiance into [0, 1] display light. The sRGB encode happens after this
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneOperator {
+    Clamp,
+    Reinhard,
+    ReinhardExtended,
+    Hable,
+    Aces,
+}
+impl ToneOperator {
+    pub const ALL: [ToneOperator; 5] = [
+        ToneOperator::Clamp,
+        ToneOperator::Reinhard,
+        ToneOperator::ReinhardExtended,
+        ToneOperator::Hable,
+        ToneOperator::Aces,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneOperator::Clamp => "clamp",
+            ToneOperator::Reinhard => "reinhard",
+            ToneOperator::ReinhardExtended => "reinhard-extended",
+            ToneOperator::Hable => "hable",
+            ToneOperator::Aces => "aces",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<ToneOperator> {
+        ToneOperator::ALL.iter().cloned().find(|op| op.name() == name)
+    }
+    // For cycling through them in the preview
+    pub fn next(self) -> ToneOperator {
+        let i = ToneOperator::ALL.iter().position(|op| *op == self).unwrap();
+        ToneOperator::ALL[(i + 1) % ToneOperator::ALL.len()]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneOperator,
+    pub exposure: f64,      // EV, every +1 doubles the light
+    pub white_point: f64,   // smallest luminance extended Reinhard maps to white
+    pub white_balance: f64, // kelvin of the light to neutralize, 6500 leaves colors alone
+}
+impl Default for ToneMapping {
+    fn default() -> ToneMapping {
+        ToneMapping {
+            operator: ToneOperator::Clamp,
+            exposure: 0.0,
+            white_point: 4.0,
+            white_balance: 6500.0,
+        }
+    }
+}
+
+pub fn luminance(c: &Color<f64>) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+// Linear sRGB color of a blackbody at t kelvin with Y = 1, from the Kim et al. fit of the Planckian locus
+pub fn blackbody_rgb(t: f64) -> Color<f64> {
+    let t = t.max(1667.0).min(25000.0);
+    let (t2, t3) = (t * t, t * t * t);
+    let x = if t <= 4000.0 {
+        -0.266_123_9e9 / t3 - 0.234_358_9e6 / t2 + 0.877_695_6e3 / t + 0.179_910
+    } else {
+        -3.025_846_9e9 / t3 + 2.107_037_9e6 / t2 + 0.222_634_7e3 / t + 0.240_390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.106_381_4 * x3 - 1.348_110_20 * x2 + 2.185_558_32 * x - 0.202_196_83
+    } else if t <= 4000.0 {
+        -0.954_947_6 * x3 - 1.374_185_93 * x2 + 2.091_370_15 * x - 0.167_488_67
+    } else {
+        3.081_758_0 * x3 - 5.873_386_70 * x2 + 3.751_129_97 * x - 0.370_014_83
+    };
+    let (cx, cy, cz) = (x / y, 1.0, (1.0 - x - y) / y);
+    Color {
+        x: 3.240_454_2 * cx - 1.537_138_5 * cy - 0.498_531_4 * cz,
+        y: -0.969_266_0 * cx + 1.876_010_8 * cy + 0.041_556_0 * cz,
+        z: 0.055_643_4 * cx - 0.204_025_9 * cy + 1.057_225_2 * cz,
+    }
+}
+
+fn hable_curve(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+impl ToneMapping {
+    // Per channel gains that make the white_balance light look like a 6500K one, keeping luminance
+    pub fn white_balance_gains(&self) -> Color<f64> {
+        let reference = blackbody_rgb(6500.0);
+        let light = blackbody_rgb(self.white_balance);
+        let gains = reference.div(&light);
+        let norm = luminance(&(gains * &light)) / luminance(&light);
+        &gains * (1.0 / norm)
+    }
+    pub fn apply(&self, c: Color<f64>) -> Color<f64> {
+        self.apply_with_gains(c, &self.white_balance_gains())
+    }
+    // apply() with the white balance worked out up front, for running over whole images
+    pub fn apply_with_gains(&self, c: Color<f64>, gains: &Color<f64>) -> Color<f64> {
+        let c = &(c * gains) * 2f64.powf(self.exposure);
+        match self.operator {
+            ToneOperator::Clamp => c,
+            ToneOperator::Reinhard | ToneOperator::ReinhardExtended => {
+                // on luminance, so saturated highlights keep their hue
+                let l = luminance(&c);
+                if l <= 0.0 {
+                    return Color::zero();
+                }
+                let mapped = if self.operator == ToneOperator::Reinhard {
+                    l / (1.0 + l)
+                } else {
+                    l * (1.0 + l / (self.white_point * self.white_point)) / (1.0 + l)
+                };
+                &c * (mapped / l)
+            }
+            ToneOperator::Hable => {
+                // Uncharted 2: exposure bias of 2 and linear white at 11.2
+                let white = hable_curve(11.2);
+                Color {
+                    x: hable_curve(2.0 * c.x.max(0.0)) / white,
+                    y: hable_curve(2.0 * c.y.max(0.0)) / white,
+                    z: hable_curve(2.0 * c.z.max(0.0)) / white,
+                }
+            }
+            ToneOperator::Aces => {
+                // Narkowicz's fit of the RRT + ODT, which expects its input scaled by 0.6
+                let aces = |x: f64| {
+                    let x = 0.6 * x.max(0.0);
+                    ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).max(0.0).min(1.0)
+                };
+                Color {
+                    x: aces(c.x),
+                    y: aces(c.y),
+                    z: aces(c.z),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators_map_grey_ramps_monotonically() {
+        for op in ToneOperator::ALL.iter() {
+            let tm = ToneMapping {
+                operator: *op,
+                ..ToneMapping::default()
+            };
+            assert_eq!(ToneOperator::from_name(op.name()), Some(*op));
+            assert_eq!(tm.apply(Color::zero()), Color::zero());
+            let mut last = 0.0;
+            for i in 1..100 {
+                let v = tm.apply(&Color::one() * (i as f64 * 0.1)).x;
+                assert!(v > last, "{:?} at {}", op, i);
+                last = v;
+            }
+        }
+        let at = |operator, grey: f64| {
+            let tm = ToneMapping {
+                operator: operator,
+                ..ToneMapping::default()
+            };
+            tm.apply(&Color::one() * grey).x
+        };
+        // the curves reach display white where they're meant to
+        assert!((at(ToneOperator::ReinhardExtended, 4.0) - 1.0).abs() < 1e-9);
+        assert!((at(ToneOperator::Hable, 5.6) - 1.0).abs() < 1e-9);
+        assert!(at(ToneOperator::Reinhard, 1000.0) < 1.0);
+        assert!(at(ToneOperator::Aces, 1000.0) <= 1.0);
+    }
+
+    #[test]
+    fn white_balance_neutralizes_the_light() {
+        let tm = ToneMapping {
+            white_balance: 3200.0,
+            ..ToneMapping::default()
+        };
+        // a 3200K light comes out the color a 6500K one has without correction
+        let lit = tm.apply(blackbody_rgb(3200.0)).div(&blackbody_rgb(6500.0));
+        assert!((lit.x - lit.y).abs() < 1e-9 && (lit.y - lit.z).abs() < 1e-9);
+        let same = ToneMapping::default().apply(Color::from_tuple((0.2, 0.4, 0.6)));
+        assert!((same.x - 0.2).abs() < 1e-9 && (same.z - 0.6).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::vectors::Vector3;
+
+// Scale, then rotate (degrees, X then Y then Z), then translate
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub translate: Vector3<f64>,
+    pub rotate: Vector3<f64>,
+    pub scale: Vector3<f64>,
+}
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            translate: Vector3::zero(),
+            rotate: Vector3::zero(),
+            scale: Vector3::one(),
+        }
+    }
+}
+impl Transform {
+    pub fn translation(t: Vector3<f64>) -> Transform {
+        Transform {
+            translate: t,
+            ..Transform::default()
+        }
+    }
+    fn rotate_vector(&self, v: Vector3<f64>) -> Vector3<f64> {
+        let (sx, cx) = self.rotate.x.to_radians().sin_cos();
+        let (sy, cy) = self.rotate.y.to_radians().sin_cos();
+        let (sz, cz) = self.rotate.z.to_radians().sin_cos();
+        let v = Vector3 {
+            x: v.x,
+            y: cx * v.y - sx * v.z,
+            z: sx * v.y + cx * v.z,
+        };
+        let v = Vector3 {
+            x: cy * v.x + sy * v.z,
+            y: v.y,
+            z: -sy * v.x + cy * v.z,
+        };
+        Vector3 {
+            x: cz * v.x - sz * v.y,
+            y: sz * v.x + cz * v.y,
+            z: v.z,
+        }
+    }
+    pub fn apply_point(&self, p: Vector3<f64>) -> Vector3<f64> {
+        self.rotate_vector(p * &self.scale) + self.translate
+    }
+    // Normals go through the inverse transpose, which for scale + rotation is just 1/scale
+    pub fn apply_normal(&self, n: Vector3<f64>) -> Vector3<f64> {
+        if n.sqrmagnitude() == 0.0 {
+            return n;
+        }
+        self.rotate_vector(n.div(&self.scale)).normalized()
+    }
+}
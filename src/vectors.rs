@@ -1,11 +1,8 @@
-use rand::prelude::*;
-use rand::rngs::StdRng;
-use rand::{Rng, SeedableRng};
+use rand::Rng;
 use std::convert::Into;
 use std::fmt::Display;
 use std::iter::Sum;
 use std::ops::{Add, Div, Mul, Sub};
-use std::time::SystemTime;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Vector3<T>
@@ -50,12 +47,7 @@ where
             z: t[2],
         }
     }
-    pub fn random_in_unit_disk() -> Vector3<f64> {
-        let d = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .expect("TIME FAILED");
-        let mut rng = StdRng::from_entropy();
-
+    pub fn random_in_unit_disk<R: Rng + ?Sized>(rng: &mut R) -> Vector3<f64> {
         loop {
             let p = Vector3::from_tuple((rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0), 0.0));
             if p.sqrmagnitude() >= 1.0 {
@@ -64,11 +56,7 @@ where
             return p;
         }
     }
-    pub fn random_unit_vector() -> Vector3<f64> {
-        let d = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .expect("TIME FAILED");
-        let mut rng = StdRng::from_entropy();
+    pub fn random_unit_vector<R: Rng + ?Sized>(rng: &mut R) -> Vector3<f64> {
         let a = rng.gen_range(0.0, std::f64::consts::PI * 2.0);
         let z = rng.gen_range(-1.0, 1.0);
         let r = ((1.0 - (z * z)) as f64).sqrt();
@@ -78,8 +66,8 @@ where
             z: z,
         };
     }
-    pub fn random_in_hemisphere(n: Vector3<f64>) -> Vector3<f64> {
-        let in_unit_sphere = Vector3::random_in_unitsphere();
+    pub fn random_in_hemisphere<R: Rng + ?Sized>(n: Vector3<f64>, rng: &mut R) -> Vector3<f64> {
+        let in_unit_sphere = Vector3::random_in_unitsphere(rng);
         match in_unit_sphere.dot(&n) > 0.0 // In the same hemisphere as the normal
         {    true => in_unit_sphere,
             false => &in_unit_sphere * -1.0}
@@ -94,15 +82,39 @@ where
         let out_par = ((1.0 - out_perp.sqrmagnitude()).abs().sqrt() * -1.0) * n;
         return out_perp + out_par;
     }
-    pub fn random_in_unitsphere() -> Vector3<T> {
+    pub fn random_in_unitsphere<R: Rng + ?Sized>(rng: &mut R) -> Vector3<T> {
         loop {
-            let p = Vector3::random_range(-1.0, 1.0);
+            let p = Vector3::random_range(-1.0, 1.0, rng);
             if p.sqrmagnitude() >= 1.0 {
                 continue;
             }
             return p;
         }
     }
+    // Warps of uniform [0,1)^2 samples, for the samplers. No rejection loops, so a sample always
+    // uses the same number of dimensions
+    pub fn unit_vector_from(u: (f64, f64)) -> Vector3<f64> {
+        let z = 1.0 - 2.0 * u.0;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let a = 2.0 * std::f64::consts::PI * u.1;
+        Vector3 {
+            x: r * a.cos(),
+            y: r * a.sin(),
+            z: z,
+        }
+    }
+    pub fn in_unit_disk_from(u: (f64, f64)) -> Vector3<f64> {
+        let r = u.0.sqrt();
+        let a = 2.0 * std::f64::consts::PI * u.1;
+        Vector3 {
+            x: r * a.cos(),
+            y: r * a.sin(),
+            z: 0.0,
+        }
+    }
+    pub fn in_unit_ball_from(u: (f64, f64), w: f64) -> Vector3<f64> {
+        &Vector3::<f64>::unit_vector_from(u) * w.cbrt()
+    }
     pub fn zero() -> Vector3<T> {
         Vector3 {
             x: (0.0).into(),
@@ -119,24 +131,19 @@ where
     }
     pub fn neg_inf() -> Vector3<T> {
         Vector3 {
-            x: (f64::INFINITY).into(),
-            y: (f64::INFINITY).into(),
-            z: (f64::INFINITY).into(),
+            x: (f64::NEG_INFINITY).into(),
+            y: (f64::NEG_INFINITY).into(),
+            z: (f64::NEG_INFINITY).into(),
         }
     }
-    pub fn random() -> Vector3<T> {
+    pub fn random<R: Rng + ?Sized>(rng: &mut R) -> Vector3<T> {
         Vector3 {
-            x: thread_rng().gen::<f64>().into(),
-            y: thread_rng().gen::<f64>().into(),
-            z: thread_rng().gen::<f64>().into(),
+            x: rng.gen::<f64>().into(),
+            y: rng.gen::<f64>().into(),
+            z: rng.gen::<f64>().into(),
         }
     }
-    pub fn random_range(min: f64, max: f64) -> Vector3<T> {
-        let d = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .expect("TIME FAILED");
-        let mut rng = StdRng::from_entropy();
-
+    pub fn random_range<R: Rng + ?Sized>(min: f64, max: f64, rng: &mut R) -> Vector3<T> {
         Vector3 {
             x: rng.gen_range(min, max).into(),
             y: rng.gen_range(min, max).into(),
@@ -0,0 +1,183 @@
+use crate::hit::*;
+use crate::light::LightList;
+use crate::material::*;
+use crate::mesh::TriangleMesh;
+use crate::objLoader;
+use crate::render::TRI_COUNT;
+use crate::sceneLoader::{ObjectDescription, SceneDescription};
+use crate::transform::Transform;
+use crate::vectors::Vector3;
+use crate::vectors::Vector3 as Color;
+use crate::BVH::Bounds;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+pub struct World{
+    pub objects: HittableList,
+    pub materials: HashMap<String, Arc<dyn Material + Send + Sync >>,
+    pub lights: LightList,
+    // Ids for the ID AOVs. Materials are numbered by name in the order they were first added,
+    // objects by when they were added, and object_materials is each object's material id
+    pub material_ids: HashMap<String, u32>,
+    pub object_materials: Vec<u32>,
+}
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum matTypes {
+    lambert,
+    metal,
+    dialectric,
+    emissive,
+    normal,
+}
+impl World{
+    pub fn new() -> World {
+        World {
+            objects: HittableList {
+                objects: vec![],
+                bbox: Bounds::infinity(),
+            },
+            materials: HashMap::new(),
+            lights: LightList::new(),
+            material_ids: HashMap::new(),
+            object_materials: vec![],
+        }
+    }
+    // Id for the next object added, which uses material mat
+    fn next_object_id(&mut self, mat: &str) -> u32 {
+        self.object_materials.push(self.material_ids[mat]);
+        (self.object_materials.len() - 1) as u32
+    }
+    pub fn addMat(
+        &mut self,
+        name: String,
+        m: matTypes,
+        col: (f64, f64, f64),
+        rough: f64,
+        ior: f64,
+    ) {
+        let c = Color {
+            x: col.0,
+            y: col.1,
+            z: col.2,
+        };
+        let material = match m {
+            matTypes::normal => Arc::new(Normal {}) as Arc<dyn Material + Send + Sync>,
+            matTypes::lambert => Arc::new(Lambert { albedo: c }) as Arc<dyn Material + Send + Sync>,
+            matTypes::metal => Arc::new(Metal {
+                albedo: c,
+                fuzz: rough,
+            }) as Arc<dyn Material + Send + Sync>,
+            matTypes::dialectric => Arc::new(Dialectric {
+                albedo: c,
+                fuzz: rough,
+                ref_idx: ior,
+            }) as Arc<dyn Material + Send + Sync>,
+            matTypes::emissive => Arc::new(Emissive {
+                albedo: c,
+                emission: rough,
+            }),
+        };
+        let id = self.material_ids.len() as u32;
+        self.material_ids.entry(name.clone()).or_insert(id);
+        self.materials.insert(name, material);
+    }
+    pub fn addSphere(&mut self, p: (f64, f64, f64), r: f64, mat: String) {
+        let id = self.next_object_id(&mat);
+        let m = self.materials.get(&mat).unwrap();
+        if m.is_light() {
+            self.lights.add_sphere(Vector3::from_tuple(p), r, Arc::clone(m), id);
+        }
+        self.objects.add(Arc::new(Sphere {
+            center: Vector3::from_tuple(p),
+            radius: r,
+            mat: Arc::clone(m),
+            bbox: Bounds::fromSphere(Vector3::from_tuple(p), r),
+            id: id,
+        }));
+    }
+    #[rustfmt::skip]
+    pub fn addTri(
+        &mut self,
+        p0: (f64, f64, f64),
+        p1: (f64, f64, f64),
+        p2: (f64, f64, f64),
+        mat: String,
+    ) {
+        let id = self.next_object_id(&mat);
+        let m = self.materials.get(&mat).unwrap();
+        let p0 = Vector3::from_tuple(p0);
+        let p1 = Vector3::from_tuple(p1);
+        let p2 = Vector3::from_tuple(p2);
+        if m.is_light() {
+            self.lights.add_tri(p0, p1, p2, Arc::clone(m), id);
+        }
+        let mut bbox = Bounds::new();
+        bbox.fitPoints(vec![p0, p1, p2]);
+        // a lone triangle is flat, so every corner gets the face normal
+        let n = objLoader::face_normal(&p0, &p1, &p2);
+        self.objects.add(Arc::new(Tri {
+            v0: Vert { P: p0, UV: Vector3::zero(), N: n},
+            v1: Vert { P: p1, UV: Vector3::zero(), N: n},
+            v2: Vert { P: p2, UV: Vector3::zero(), N: n},
+            mat: Arc::clone(m),
+            bbox: bbox,
+            id: id,
+            cull_backfaces: false,
+        }))
+    }
+    // The whole mesh is one object sharing one set of vertices, with its own BVH
+    pub fn addTriMesh(
+        &mut self,
+        tris: &Vec<objLoader::TriData>,
+        xform: &Transform,
+        mat: String,
+        cull_backfaces: bool,
+    ) {
+        let id = self.next_object_id(&mat);
+        let m = self.materials.get(&mat).unwrap();
+        let mesh = TriangleMesh::from_tris(tris, xform, Arc::clone(m), id, cull_backfaces);
+        TRI_COUNT.fetch_add(mesh.triangle_count(), Ordering::Relaxed);
+        if m.is_light() {
+            for tri in 0..mesh.triangle_count() as u32 {
+                let [i0, i1, i2] = mesh.corners(tri);
+                let p = &mesh.positions;
+                self.lights.add_tri(p[i0], p[i1], p[i2], Arc::clone(m), id);
+            }
+        }
+        self.objects.add(Arc::new(mesh));
+    }
+    // Builds a World out of a parsed scene file, loading any meshes it references
+    #[rustfmt::skip]
+    pub fn from_description(desc: &SceneDescription) -> Result<World, String> {
+        let mut world = World::new();
+        for m in desc.materials.iter() {
+            let rough = match m.kind {
+                matTypes::emissive => m.emission,
+                _ => m.fuzz,
+            };
+            world.addMat(m.name.clone(), m.kind, (m.color.x, m.color.y, m.color.z), rough, m.ior);
+        }
+        for o in desc.objects.iter() {
+            match o {
+                ObjectDescription::Sphere { center, radius, material } => {
+                    world.addSphere((center.x, center.y, center.z), *radius, material.clone())
+                }
+                ObjectDescription::Tri { p0, p1, p2, material } => world.addTri(
+                    (p0.x, p0.y, p0.z),
+                    (p1.x, p1.y, p1.z),
+                    (p2.x, p2.y, p2.z),
+                    material.clone(),
+                ),
+                ObjectDescription::Mesh { file, material, transform, cull_backfaces, smooth } => {
+                    let mut tris = objLoader::load_obj(file).map_err(|e| e.to_string())?;
+                    if let Some(crease) = smooth {
+                        objLoader::generate_normals(&mut tris, *crease);
+                    }
+                    world.addTriMesh(&tris, transform, material.clone(), *cull_backfaces);
+                }
+            }
+        }
+        return Ok(world);
+    }
+}